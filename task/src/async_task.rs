@@ -0,0 +1,487 @@
+use uuid::Uuid;
+
+use crate::executor::{join_limited, BlockingExecutor, BoxFuture, Executor};
+use crate::task::Command;
+use crate::{
+    CallOutcome, CancellationToken, EventKind, Holds, Invocation, Invoker, Operation, Running,
+    Task, TaskManager, Threading,
+};
+
+pub trait AsyncTask<T, U, R, E> {
+    fn uuid(&self) -> Uuid;
+    fn execute<'a>(&'a mut self, data: &'a mut T, arg: &'a mut U) -> BoxFuture<'a, Result<R, E>>;
+    fn rollback<'a>(&'a mut self, data: &'a mut T, arg: &'a mut U) -> BoxFuture<'a, Result<R, E>>;
+
+    // Tasks that can stop early should override this and poll `token`.
+    fn execute_with_token<'a>(
+        &'a mut self,
+        data: &'a mut T,
+        arg: &'a mut U,
+        _token: &'a CancellationToken,
+    ) -> BoxFuture<'a, Result<R, E>> {
+        self.execute(data, arg)
+    }
+}
+
+// Lets an `AsyncTask` sit in an `Invoker`: `execute_async` and the like
+// await it, the synchronous methods block on it.
+struct AsyncCommand<X>(X);
+
+impl<X, T, U, R, E> Command<T, U, R, E> for AsyncCommand<X>
+where
+    X: AsyncTask<T, U, R, E>,
+{
+    fn uuid(&self) -> Uuid {
+        self.0.uuid()
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        BlockingExecutor.block_on(self.0.execute(data, arg))
+    }
+
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        BlockingExecutor.block_on(self.0.rollback(data, arg))
+    }
+
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        BlockingExecutor.block_on(self.0.execute_with_token(data, arg, token))
+    }
+
+    fn execute_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+        token: &'f CancellationToken,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        self.0.execute_with_token(data, arg, token)
+    }
+
+    fn rollback_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        self.0.rollback(data, arg)
+    }
+}
+
+impl<'a, T, U, R, E> Invoker<'a, T, U, R, E>
+where
+    R: Default,
+{
+    pub fn push_async<X: AsyncTask<T, U, R, E> + 'a>(&mut self, task: X) -> &mut Self {
+        self.push(AsyncCommand(task))
+    }
+}
+
+impl<T, U, R, E, M: Threading> Task<T, U, R, E, M> {
+    pub fn set_async_callback<F>(&mut self, callback: F)
+    where
+        F: for<'a> Fn(&'a mut Self, &'a U) -> BoxFuture<'a, Result<R, E>> + 'static,
+        M: Holds<F>,
+    {
+        self.do_async_callback = Some(M::async_callback(callback));
+    }
+
+    // Falls back to the synchronous callback when no async callback is set.
+    pub async fn call_async(&mut self, data: &U) -> Result<R, E> {
+        self.called += 1;
+
+        match self.do_async_callback.clone() {
+            Some(cb) => cb(self, data).await,
+            None => {
+                let cb = self.do_callback.clone();
+                cb(self, data)
+            }
+        }
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    // Runs the queue in waves: every task that is ready at the start of a wave
+    // runs concurrently (at most `limit` at a time), and tasks depending on
+    // them become eligible in the next wave. Finished calls are retried,
+    // scheduled again or dead-lettered as in `call_all`.
+    pub async fn call_concurrent(
+        &mut self,
+        data: &U,
+        limit: usize,
+    ) -> Vec<CallOutcome<T, U, R, E, M>> {
+        let mut result = Vec::new();
+
+        loop {
+            self.expire();
+            self.sweep_cancelled();
+            self.sweep_dependents();
+            self.release_results();
+            self.refill_quotas();
+
+            if self.paused {
+                break;
+            }
+
+            // the wave counts as running, so the quotas see it in flight
+            let mut wave = Vec::new();
+
+            for uuid in self.ready() {
+                if let Some(mut task) = self.admit(uuid) {
+                    self.attach_input(&mut task);
+                    self.running.insert(task.uuid, Running::of(&task));
+                    wave.push(task);
+                }
+            }
+
+            if wave.is_empty() {
+                break;
+            }
+
+            self.update_gauges();
+
+            let invocations = wave
+                .iter()
+                .map(|e| Invocation {
+                    uuid: e.uuid,
+                    operation: Operation::Call,
+                    attempt: e.called + 1,
+                })
+                .collect::<Vec<_>>();
+
+            for (task, invocation) in wave.iter().zip(&invocations) {
+                task.token.set_timeout(&self.clock, task.timeout);
+                self.emit(task.uuid, EventKind::Started, invocation.attempt);
+            }
+
+            let futures = wave
+                .iter_mut()
+                .zip(&invocations)
+                .map(|(task, invocation)| {
+                    self.middleware
+                        .run_async(invocation, Box::pin(task.call_async(data)))
+                })
+                .collect();
+
+            let called = join_limited(futures, limit).await;
+
+            for (task, called) in wave.into_iter().zip(called) {
+                result.push(self.finish_call(task, called));
+            }
+        }
+
+        result
+    }
+
+    pub fn call_concurrent_blocking(
+        &mut self,
+        executor: &impl Executor,
+        data: &U,
+        limit: usize,
+    ) -> Vec<CallOutcome<T, U, R, E, M>> {
+        executor.block_on(self.call_concurrent(data, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::executor::yield_now;
+    use crate::{Event, Hooks, RetryPolicy, Schedule};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct DelayedAdd {
+        uuid: Uuid,
+        amount: i32,
+        fail: bool,
+    }
+
+    impl DelayedAdd {
+        fn new(amount: i32) -> Self {
+            Self {
+                uuid: Uuid::new_v4(),
+                amount,
+                fail: false,
+            }
+        }
+    }
+
+    impl AsyncTask<i32, i32, i32, String> for DelayedAdd {
+        fn uuid(&self) -> Uuid {
+            self.uuid
+        }
+
+        fn execute<'a>(
+            &'a mut self,
+            data: &'a mut i32,
+            arg: &'a mut i32,
+        ) -> BoxFuture<'a, Result<i32, String>> {
+            Box::pin(async move {
+                yield_now().await;
+
+                if self.fail {
+                    return Err(format!("failed {}", self.amount));
+                }
+
+                *arg += 1;
+                *data += self.amount;
+                Ok(*data)
+            })
+        }
+
+        fn rollback<'a>(
+            &'a mut self,
+            data: &'a mut i32,
+            _arg: &'a mut i32,
+        ) -> BoxFuture<'a, Result<i32, String>> {
+            Box::pin(async move {
+                yield_now().await;
+
+                *data -= self.amount;
+                Ok(*data)
+            })
+        }
+    }
+
+    #[test]
+    fn test_async_invoker() {
+        let mut target = 0;
+        let events = RefCell::new(Vec::new());
+        let mut invoker = Invoker::new(&mut target);
+
+        let mut failing = DelayedAdd::new(4);
+        failing.fail = true;
+
+        invoker.subscribe(|e: &Event| events.borrow_mut().push(e.kind));
+        invoker.push_async(DelayedAdd::new(1));
+        invoker.push_async(DelayedAdd::new(2));
+        invoker.push_async(failing);
+
+        let executor = BlockingExecutor;
+        let mut count = 0;
+
+        assert_eq!(Ok(1), executor.block_on(invoker.execute_async(&mut count)));
+        // blocked on outside of async code
+        assert_eq!(Ok(3), invoker.execute(&mut count));
+        assert_eq!(
+            Err("failed 4".to_string()),
+            executor.block_on(invoker.execute_async(&mut count))
+        );
+        assert_eq!(Ok(1), executor.block_on(invoker.undo_async(&mut count)));
+        assert!(invoker.can_redo());
+        assert_eq!(2, count);
+        assert_eq!(&1, invoker.data());
+        assert_eq!(
+            vec![
+                EventKind::Queued,
+                EventKind::Queued,
+                EventKind::Queued,
+                EventKind::Started,
+                EventKind::Succeeded,
+                EventKind::Started,
+                EventKind::Succeeded,
+                EventKind::Started,
+                EventKind::Failed,
+                EventKind::RolledBack,
+            ],
+            *events.borrow()
+        );
+    }
+
+    struct Cancellable(Uuid);
+
+    impl AsyncTask<i32, i32, i32, String> for Cancellable {
+        fn uuid(&self) -> Uuid {
+            self.0
+        }
+
+        fn execute<'a>(
+            &'a mut self,
+            data: &'a mut i32,
+            _arg: &'a mut i32,
+        ) -> BoxFuture<'a, Result<i32, String>> {
+            Box::pin(async move {
+                *data += 1;
+                Ok(*data)
+            })
+        }
+
+        fn rollback<'a>(
+            &'a mut self,
+            data: &'a mut i32,
+            _arg: &'a mut i32,
+        ) -> BoxFuture<'a, Result<i32, String>> {
+            Box::pin(async move {
+                *data -= 1;
+                Ok(*data)
+            })
+        }
+
+        fn execute_with_token<'a>(
+            &'a mut self,
+            data: &'a mut i32,
+            arg: &'a mut i32,
+            token: &'a CancellationToken,
+        ) -> BoxFuture<'a, Result<i32, String>> {
+            Box::pin(async move {
+                yield_now().await;
+
+                match token.interrupt() {
+                    Some(e) => Err(e.to_string()),
+                    None => self.execute(data, arg).await,
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_async_token() {
+        let mut target = 0;
+        let mut invoker = Invoker::new(&mut target);
+        let mut arg = 0;
+
+        invoker.push_async(Cancellable(Uuid::new_v4()));
+        invoker.push_async(Cancellable(Uuid::new_v4()));
+
+        assert_eq!(
+            Ok(1),
+            BlockingExecutor.block_on(invoker.execute_async(&mut arg))
+        );
+
+        invoker.token().cancel();
+
+        assert_eq!(
+            Err("cancelled".to_string()),
+            BlockingExecutor.block_on(invoker.execute_async(&mut arg))
+        );
+        assert_eq!(&1, invoker.data());
+    }
+
+    #[test]
+    fn test_call_concurrent() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut manager = TaskManager::<_, _, (), i32>::new();
+        let mut uuids = Vec::new();
+
+        for i in 0..4 {
+            let mut task = Task::new(i);
+            let log = log.clone();
+
+            task.set_async_callback(move |this, arg| {
+                let log = log.clone();
+
+                Box::pin(async move {
+                    log.borrow_mut().push(format!("start {}", this.data()));
+                    yield_now().await;
+                    log.borrow_mut().push(format!("end {}", this.data()));
+
+                    if this.data() != arg {
+                        Ok(())
+                    } else {
+                        Err(*arg)
+                    }
+                })
+            });
+
+            uuids.push(task.uuid());
+            manager.push(task);
+        }
+
+        // the last task runs after every other one
+        for i in 0..3 {
+            manager.add_dependency(uuids[3], uuids[i]).unwrap();
+        }
+
+        let after = log.clone();
+
+        manager.add_middleware(Hooks::new().after(move |_, result: &Result<(), i32>, _| {
+            after.borrow_mut().push(format!("after {:?}", result));
+        }));
+
+        let result = manager.call_concurrent_blocking(&BlockingExecutor, &1, 2);
+
+        // the last task never runs, as one it depends on failed
+        assert!(manager.is_empty());
+        assert_eq!(uuids[3], manager.dead_letters()[0].uuid());
+        assert_eq!(
+            vec![(0, Ok(())), (1, Err(1)), (2, Ok(()))],
+            result
+                .iter()
+                .map(|e| (*e.task.as_ref().unwrap().data(), e.result))
+                .collect::<Vec<_>>()
+        );
+        assert!(result
+            .iter()
+            .all(|e| e.task.as_ref().unwrap().called() == 1));
+        assert_eq!(
+            vec![
+                "start 0",
+                "start 1",
+                "end 0",
+                "after Ok(())",
+                "end 1",
+                "after Err(1)",
+                "start 2",
+                "end 2",
+                "after Ok(())",
+            ],
+            *log.borrow()
+        );
+    }
+
+    #[test]
+    fn test_call_concurrent_policies() {
+        let clock = Arc::new(ManualClock::default());
+        let mut manager = TaskManager::<_, (), (), ()>::with_clock(clock.clone());
+        let mut failing = Task::new(0);
+        let mut recurring = Task::new(1);
+
+        failing.set_callback(|_, _| Err(()));
+        recurring
+            .set_schedule(Some(Schedule::Every(Duration::from_secs(1))))
+            .unwrap();
+        manager.set_retry_policy(Some(RetryPolicy::new(3)));
+        manager.push(failing);
+        manager.push(recurring);
+        clock.advance(Duration::from_secs(1));
+
+        let result = manager.call_concurrent_blocking(&BlockingExecutor, &(), 2);
+
+        // the failing task is retried until it runs out of attempts, while the
+        // recurring one waits for its next run
+        assert_eq!(4, result.len());
+        assert_eq!(1, manager.dead_letters().len());
+        assert_eq!(3, manager.dead_letters()[0].called());
+        assert_eq!(1, manager.len());
+        assert_eq!(&1, manager.iter().next().unwrap().data());
+    }
+
+    #[test]
+    fn test_call_async_fallback() {
+        let mut task = Task::<_, _, i32, ()>::new(1);
+
+        task.set_callback(|this, arg| {
+            this.data += *arg;
+
+            Ok(this.data)
+        });
+
+        assert_eq!(Ok(3), BlockingExecutor.block_on(task.call_async(&2)));
+        assert_eq!(&3, task.data());
+        assert_eq!(1, task.called());
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::Clock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Cancelled,
+    TimedOut,
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Cancelled => "cancelled",
+            Self::TimedOut => "timed out",
+        })
+    }
+}
+
+struct Deadline {
+    at: SystemTime,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    deadline: Mutex<Option<Deadline>>,
+    parent: Option<CancellationToken>,
+}
+
+// Cancellation is cooperative: a running task is expected to poll its token
+// and return early once it reports an interrupt.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A child is interrupted along with its parent, but cancelling the child
+    // leaves the parent untouched.
+    pub fn child(&self) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                parent: Some(self.clone()),
+                ..Inner::default()
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.interrupt().is_some()
+    }
+
+    pub fn interrupt(&self) -> Option<Interrupt> {
+        if self.inner.cancelled.load(Ordering::SeqCst) {
+            return Some(Interrupt::Cancelled);
+        }
+
+        let timed_out = self
+            .inner
+            .deadline
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|e| e.clock.now() >= e.at);
+
+        if timed_out {
+            return Some(Interrupt::TimedOut);
+        }
+
+        self.inner.parent.as_ref().and_then(|e| e.interrupt())
+    }
+
+    // A timeout too long to fall within the clock's range is no deadline.
+    pub(crate) fn set_timeout(&self, clock: &Arc<dyn Clock>, timeout: Option<Duration>) {
+        *self.inner.deadline.lock().unwrap() = timeout.and_then(|e| {
+            Some(Deadline {
+                at: clock.now().checked_add(e)?,
+                clock: clock.clone(),
+            })
+        });
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("interrupt", &self.interrupt())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_token() {
+        let clock = Arc::new(ManualClock::default());
+        let parent = CancellationToken::new();
+        let child = parent.child();
+
+        child.set_timeout(
+            &(clock.clone() as Arc<dyn Clock>),
+            Some(Duration::from_secs(5)),
+        );
+
+        assert!(!child.is_cancelled());
+
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(Some(Interrupt::TimedOut), child.interrupt());
+        assert_eq!(None, parent.interrupt());
+
+        child.set_timeout(&(clock.clone() as Arc<dyn Clock>), Some(Duration::MAX));
+        assert_eq!(None, child.interrupt());
+
+        child.set_timeout(&(clock as Arc<dyn Clock>), None);
+        parent.cancel();
+
+        assert_eq!(Some(Interrupt::Cancelled), child.interrupt());
+        assert!(parent.is_cancelled());
+
+        let other = parent.clone();
+        assert!(other.is_cancelled());
+    }
+}
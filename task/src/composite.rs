@@ -0,0 +1,632 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::task::Command;
+use crate::CancellationToken;
+
+type Child<'a, T, U, R, E> = Box<dyn Command<T, U, R, E> + 'a>;
+type Aggregate<'a, R, E> = Box<dyn Fn(Vec<Result<R, E>>) -> Result<R, E> + 'a>;
+type Predicate<'a, T, U> = Box<dyn Fn(&T, &U) -> bool + 'a>;
+
+// Runs its children in order and stops at the first failure, rolling back the
+// children that already ran so a failed sequence leaves nothing behind.
+pub struct Sequence<'a, T, U, R, E> {
+    uuid: Uuid,
+    children: Vec<Child<'a, T, U, R, E>>,
+    rollback_failures: Vec<(Uuid, E)>,
+}
+
+impl<'a, T, U, R, E> Sequence<'a, T, U, R, E> {
+    pub fn new() -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            children: Vec::new(),
+            rollback_failures: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, task: impl Command<T, U, R, E> + 'a) -> Self {
+        self.children.push(Box::new(task));
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    // The children that failed to roll back when the last execute failed and
+    // undid the ones before it; what they did is left in place.
+    pub fn rollback_failures(&self) -> impl Iterator<Item = (&Uuid, &E)> {
+        self.rollback_failures.iter().map(|(uuid, e)| (uuid, e))
+    }
+}
+
+impl<T, U, R, E> Default for Sequence<'_, T, U, R, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, U, R, E> Command<T, U, R, E> for Sequence<'_, T, U, R, E>
+where
+    R: Default,
+{
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.execute_with_token(data, arg, &CancellationToken::new())
+    }
+
+    // Succeeds with the result of the last child.
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        let mut result = R::default();
+
+        self.rollback_failures.clear();
+
+        for i in 0..self.children.len() {
+            match self.children[i].execute_with_token(data, arg, token) {
+                Ok(e) => result = e,
+                Err(e) => {
+                    self.rollback_failures =
+                        failures(roll_back(self.children[..i].iter_mut(), data, arg));
+
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    // Every child is rolled back, in reverse, even after one of them fails;
+    // the first failure is returned.
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        first_error(
+            roll_back(self.children.iter_mut(), data, arg)
+                .into_iter()
+                .map(|(_, result)| result)
+                .collect(),
+        )
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        self.children.iter_mut().all(|e| e.dry_run(data, arg))
+    }
+
+    // Long enough for every child to use up its own timeout.
+    fn timeout(&self) -> Option<Duration> {
+        if self.children.is_empty() {
+            return None;
+        }
+
+        self.children.iter().map(|e| e.timeout()).sum()
+    }
+}
+
+// Runs every child, one after another, whether or not the ones before it
+// failed, then combines their results. If the combined result is an error,
+// the children that succeeded are rolled back in reverse.
+pub struct Group<'a, T, U, R, E> {
+    uuid: Uuid,
+    children: Vec<Child<'a, T, U, R, E>>,
+    succeeded: Vec<bool>,
+    aggregate: Aggregate<'a, R, E>,
+    rollback_failures: Vec<(Uuid, E)>,
+}
+
+impl<'a, T, U, R, E> Group<'a, T, U, R, E>
+where
+    R: Default + 'a,
+    E: 'a,
+{
+    // By default the group fails with the first error, and otherwise
+    // succeeds with the result of the last child.
+    pub fn new() -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            children: Vec::new(),
+            succeeded: Vec::new(),
+            aggregate: Box::new(first_error),
+            rollback_failures: Vec::new(),
+        }
+    }
+}
+
+impl<'a, T, U, R, E> Group<'a, T, U, R, E> {
+    pub fn with(mut self, task: impl Command<T, U, R, E> + 'a) -> Self {
+        self.children.push(Box::new(task));
+
+        self
+    }
+
+    pub fn with_aggregate(
+        mut self,
+        aggregate: impl Fn(Vec<Result<R, E>>) -> Result<R, E> + 'a,
+    ) -> Self {
+        self.aggregate = Box::new(aggregate);
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    // The children that failed to roll back when the last execute failed;
+    // what they did is left in place.
+    pub fn rollback_failures(&self) -> impl Iterator<Item = (&Uuid, &E)> {
+        self.rollback_failures.iter().map(|(uuid, e)| (uuid, e))
+    }
+
+    // Only the children that succeeded in the last execute are rolled back.
+    fn roll_back_succeeded(&mut self, data: &mut T, arg: &mut U) -> Vec<(Uuid, Result<R, E>)> {
+        let succeeded = std::mem::take(&mut self.succeeded);
+
+        roll_back(
+            self.children
+                .iter_mut()
+                .zip(succeeded)
+                .filter(|(_, succeeded)| *succeeded)
+                .map(|(e, _)| e),
+            data,
+            arg,
+        )
+    }
+}
+
+impl<'a, T, U, R, E> Default for Group<'a, T, U, R, E>
+where
+    R: Default + 'a,
+    E: 'a,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, U, R, E> Command<T, U, R, E> for Group<'_, T, U, R, E>
+where
+    R: Default,
+{
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.execute_with_token(data, arg, &CancellationToken::new())
+    }
+
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        let results = self
+            .children
+            .iter_mut()
+            .map(|e| e.execute_with_token(data, arg, token))
+            .collect::<Vec<_>>();
+
+        self.succeeded = results.iter().map(|e| e.is_ok()).collect();
+        self.rollback_failures.clear();
+
+        let result = (self.aggregate)(results);
+
+        if result.is_err() {
+            self.rollback_failures = failures(self.roll_back_succeeded(data, arg));
+        }
+
+        result
+    }
+
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        first_error(
+            self.roll_back_succeeded(data, arg)
+                .into_iter()
+                .map(|(_, result)| result)
+                .collect(),
+        )
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        self.children.iter_mut().all(|e| e.dry_run(data, arg))
+    }
+
+    // Long enough for every child to use up its own timeout.
+    fn timeout(&self) -> Option<Duration> {
+        if self.children.is_empty() {
+            return None;
+        }
+
+        self.children.iter().map(|e| e.timeout()).sum()
+    }
+}
+
+// Executes `then` when the predicate holds and `otherwise`, if any, when it
+// does not. A rollback undoes whichever branch the last execute took.
+pub struct Conditional<'a, T, U, R, E> {
+    uuid: Uuid,
+    predicate: Predicate<'a, T, U>,
+    then: Child<'a, T, U, R, E>,
+    otherwise: Option<Child<'a, T, U, R, E>>,
+    taken: Option<bool>,
+}
+
+impl<'a, T, U, R, E> Conditional<'a, T, U, R, E> {
+    pub fn new(
+        predicate: impl Fn(&T, &U) -> bool + 'a,
+        then: impl Command<T, U, R, E> + 'a,
+    ) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            predicate: Box::new(predicate),
+            then: Box::new(then),
+            otherwise: None,
+            taken: None,
+        }
+    }
+
+    pub fn with_else(mut self, otherwise: impl Command<T, U, R, E> + 'a) -> Self {
+        self.otherwise = Some(Box::new(otherwise));
+
+        self
+    }
+
+    fn branch(&mut self, then: bool) -> Option<&mut Child<'a, T, U, R, E>> {
+        if then {
+            Some(&mut self.then)
+        } else {
+            self.otherwise.as_mut()
+        }
+    }
+}
+
+impl<T, U, R, E> Command<T, U, R, E> for Conditional<'_, T, U, R, E>
+where
+    R: Default,
+{
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.execute_with_token(data, arg, &CancellationToken::new())
+    }
+
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        let then = (self.predicate)(data, arg);
+
+        self.taken = None;
+
+        let result = match self.branch(then) {
+            Some(branch) => branch.execute_with_token(data, arg, token)?,
+            None => R::default(),
+        };
+
+        self.taken = Some(then);
+
+        Ok(result)
+    }
+
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        match self.taken.take().and_then(|e| self.branch(e)) {
+            Some(branch) => branch.rollback(data, arg),
+            None => Ok(R::default()),
+        }
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        if (self.predicate)(data, arg) {
+            self.then.dry_run(data, arg)
+        } else {
+            self.otherwise.as_mut().is_none_or(|e| e.dry_run(data, arg))
+        }
+    }
+
+    // As long as the slower branch may take.
+    fn timeout(&self) -> Option<Duration> {
+        let otherwise = match &self.otherwise {
+            Some(otherwise) => otherwise.timeout()?,
+            None => Duration::ZERO,
+        };
+
+        Some(self.then.timeout()?.max(otherwise))
+    }
+}
+
+// Rolls back `children` in reverse order.
+fn roll_back<'c, C, T, U, R, E>(
+    children: impl DoubleEndedIterator<Item = &'c mut Box<C>>,
+    data: &mut T,
+    arg: &mut U,
+) -> Vec<(Uuid, Result<R, E>)>
+where
+    C: Command<T, U, R, E> + ?Sized + 'c,
+{
+    children
+        .rev()
+        .map(|e| (e.uuid(), e.rollback(data, arg)))
+        .collect()
+}
+
+fn failures<R, E>(results: Vec<(Uuid, Result<R, E>)>) -> Vec<(Uuid, E)> {
+    results
+        .into_iter()
+        .filter_map(|(uuid, result)| result.err().map(|e| (uuid, e)))
+        .collect()
+}
+
+fn first_error<R: Default, E>(results: Vec<Result<R, E>>) -> Result<R, E> {
+    results
+        .into_iter()
+        .try_fold(R::default(), |_, result| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Invoker;
+
+    // Adds to the data and logs each call to the argument.
+    struct Add {
+        uuid: Uuid,
+        amount: i32,
+        fail: bool,
+        stuck: bool,
+        timeout: Option<Duration>,
+    }
+
+    impl Add {
+        fn new(amount: i32) -> Self {
+            Self {
+                uuid: Uuid::new_v4(),
+                amount,
+                fail: false,
+                stuck: false,
+                timeout: None,
+            }
+        }
+
+        fn failing(amount: i32) -> Self {
+            Self {
+                fail: true,
+                ..Self::new(amount)
+            }
+        }
+
+        // cannot be rolled back
+        fn stuck(amount: i32) -> Self {
+            Self {
+                stuck: true,
+                ..Self::new(amount)
+            }
+        }
+    }
+
+    impl Command<i32, Vec<String>, i32, String> for Add {
+        fn uuid(&self) -> Uuid {
+            self.uuid
+        }
+
+        fn execute(&mut self, data: &mut i32, log: &mut Vec<String>) -> Result<i32, String> {
+            if self.fail {
+                return Err(format!("execute {}", self.amount));
+            }
+
+            log.push(format!("execute {}", self.amount));
+            *data += self.amount;
+            Ok(*data)
+        }
+
+        fn rollback(&mut self, data: &mut i32, log: &mut Vec<String>) -> Result<i32, String> {
+            if self.stuck {
+                return Err(format!("rollback {}", self.amount));
+            }
+
+            log.push(format!("rollback {}", self.amount));
+            *data -= self.amount;
+            Ok(*data)
+        }
+
+        fn dry_run(&mut self, _data: &mut i32, _log: &Vec<String>) -> bool {
+            !self.fail
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+    }
+
+    #[test]
+    fn test_sequence() {
+        let (mut data, mut log) = (0, Vec::new());
+        let mut sequence = Sequence::new().with(Add::new(1)).with(Add::new(2));
+
+        assert_eq!(2, sequence.len());
+        assert_eq!(Ok(3), sequence.execute(&mut data, &mut log));
+        assert_eq!(Ok(0), sequence.rollback(&mut data, &mut log));
+        assert_eq!(
+            vec!["execute 1", "execute 2", "rollback 2", "rollback 1"],
+            log
+        );
+
+        let mut sequence = sequence.with(Add::failing(3)).with(Add::new(4));
+        log.clear();
+
+        assert!(!sequence.dry_run(&mut data, &log));
+        assert_eq!(
+            Err("execute 3".to_string()),
+            sequence.execute(&mut data, &mut log)
+        );
+        assert_eq!(0, data);
+        assert_eq!(
+            vec!["execute 1", "execute 2", "rollback 2", "rollback 1"],
+            log
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        let (mut data, mut log) = (0, Vec::new());
+        let mut group = Group::new()
+            .with(Add::new(1))
+            .with(Add::failing(2))
+            .with(Add::new(4));
+
+        // the children after the failing one still run, and are undone with
+        // the rest
+        assert_eq!(
+            Err("execute 2".to_string()),
+            group.execute(&mut data, &mut log)
+        );
+        assert_eq!(0, data);
+        assert_eq!(
+            vec!["execute 1", "execute 4", "rollback 4", "rollback 1"],
+            log
+        );
+
+        // succeeds as long as any child does, with the number that did
+        let mut group =
+            group.with_aggregate(
+                |results| match results.iter().filter(|e| e.is_ok()).count() {
+                    0 => Err("none succeeded".to_string()),
+                    count => Ok(count as i32),
+                },
+            );
+        log.clear();
+
+        assert_eq!(Ok(2), group.execute(&mut data, &mut log));
+        assert_eq!(5, data);
+        assert_eq!(Ok(0), group.rollback(&mut data, &mut log));
+        assert_eq!(0, data);
+        assert_eq!(
+            vec!["execute 1", "execute 4", "rollback 4", "rollback 1"],
+            log
+        );
+    }
+
+    #[test]
+    fn test_rollback_failures() {
+        let (mut data, mut log) = (0, Vec::new());
+        let stuck = Add::stuck(1);
+        let uuid = stuck.uuid;
+        let mut sequence = Sequence::new()
+            .with(stuck)
+            .with(Add::new(2))
+            .with(Add::failing(3));
+
+        assert_eq!(
+            Err("execute 3".to_string()),
+            sequence.execute(&mut data, &mut log)
+        );
+        assert_eq!(1, data);
+        assert_eq!(
+            vec![(&uuid, &"rollback 1".to_string())],
+            sequence.rollback_failures().collect::<Vec<_>>()
+        );
+
+        let stuck = Add::stuck(4);
+        let uuid = stuck.uuid;
+        let mut group = Group::new().with(stuck).with(Add::failing(5));
+
+        assert!(group.execute(&mut data, &mut log).is_err());
+        assert_eq!(5, data);
+        assert_eq!(
+            vec![(&uuid, &"rollback 4".to_string())],
+            group.rollback_failures().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (a, b) = (Duration::from_secs(1), Duration::from_secs(2));
+        let job = |timeout| Add {
+            timeout,
+            ..Add::new(0)
+        };
+
+        assert_eq!(
+            Some(a + b),
+            Sequence::new()
+                .with(job(Some(a)))
+                .with(job(Some(b)))
+                .timeout()
+        );
+        assert_eq!(
+            Some(a + b),
+            Group::new().with(job(Some(a))).with(job(Some(b))).timeout()
+        );
+        // one child without a timeout could take as long as it likes
+        assert_eq!(
+            None,
+            Group::new().with(job(Some(a))).with(job(None)).timeout()
+        );
+        assert_eq!(
+            Some(a),
+            Conditional::new(|_: &_, _: &_| true, job(Some(a))).timeout()
+        );
+    }
+
+    #[test]
+    fn test_conditional() {
+        let (mut data, mut log) = (0, Vec::new());
+        let mut conditional =
+            Conditional::new(|data: &i32, _: &_| *data < 10, Add::new(5)).with_else(Add::new(-5));
+
+        assert_eq!(Ok(5), conditional.execute(&mut data, &mut log));
+        assert_eq!(Ok(10), conditional.execute(&mut data, &mut log));
+        assert_eq!(Ok(5), conditional.execute(&mut data, &mut log));
+        // only the branch the last execute took is undone, and only once
+        assert_eq!(Ok(10), conditional.rollback(&mut data, &mut log));
+        assert_eq!(Ok(0), conditional.rollback(&mut data, &mut log));
+        assert_eq!(
+            vec!["execute 5", "execute 5", "execute -5", "rollback -5"],
+            log
+        );
+
+        let mut conditional = Conditional::new(|_: &_, _: &_| false, Add::new(1));
+
+        assert_eq!(Ok(0), conditional.execute(&mut data, &mut log));
+        assert_eq!(10, data);
+    }
+
+    #[test]
+    fn test_invoker() {
+        let mut data = 0;
+        let mut log = Vec::new();
+        let mut invoker = Invoker::new(&mut data);
+
+        invoker.push(
+            Sequence::new()
+                .with(Add::new(1))
+                .with(Conditional::new(|data: &i32, _: &_| *data > 0, Add::new(2))),
+        );
+
+        assert_eq!(Ok(3), invoker.execute(&mut log));
+        assert_eq!(Ok(0), invoker.undo(&mut log));
+        assert_eq!(&0, invoker.data());
+    }
+}
@@ -0,0 +1,254 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::{EventKind, Task, TaskManager, Threading};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushError {
+    // a task with the key is queued or running
+    Pending { key: String, uuid: Uuid },
+    // a task with the key succeeded within the dedup window
+    Completed { key: String },
+    // the manager is draining and takes no new tasks
+    Draining,
+    // `push_piped` with nothing queued to take the output of
+    NoSource,
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending { key, uuid } => {
+                write!(f, "task with key {} is already pending: {}", key, uuid)
+            }
+            Self::Completed { key } => write!(f, "task with key {} already completed", key),
+            Self::Draining => write!(f, "task manager is draining"),
+            Self::NoSource => write!(f, "no task queued to pipe from"),
+        }
+    }
+}
+
+impl Error for PushError {}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    pub fn dedup_window(&self) -> Duration {
+        self.dedup_window
+    }
+
+    // How long the key of a task that succeeded keeps new submissions out.
+    // Zero, the default, only deduplicates against pending tasks.
+    pub fn set_dedup_window(&mut self, window: Duration) {
+        self.dedup_window = window;
+
+        if window.is_zero() {
+            self.completed_keys.clear();
+        }
+    }
+
+    // Like `push`, but a task that is turned away is reported rather than
+    // silently dropped.
+    pub fn try_push(&mut self, task: Task<T, U, R, E, M>) -> Result<Uuid, PushError> {
+        self.check_push(&task)?;
+
+        let uuid = task.uuid;
+
+        self.emit(uuid, EventKind::Queued, task.called);
+        self.enqueue(task);
+        self.update_gauges();
+
+        Ok(uuid)
+    }
+
+    pub(crate) fn check_push(&mut self, task: &Task<T, U, R, E, M>) -> Result<(), PushError> {
+        if self.draining {
+            return Err(PushError::Draining);
+        }
+
+        match &task.key {
+            Some(key) => self.check_key(key),
+            None => Ok(()),
+        }
+    }
+
+    fn check_key(&mut self, key: &str) -> Result<(), PushError> {
+        let pending = self
+            .tasks
+            .iter()
+            .filter(|e| e.key() == Some(key))
+            .map(|e| e.uuid)
+            .chain(
+                self.running
+                    .iter()
+                    .filter(|(_, e)| e.key.as_deref() == Some(key))
+                    .map(|(uuid, _)| *uuid),
+            )
+            .next();
+
+        if let Some(uuid) = pending {
+            return Err(PushError::Pending {
+                key: key.to_string(),
+                uuid,
+            });
+        }
+
+        let now = self.clock.now();
+        let window = self.dedup_window;
+
+        // a window reaching past the clock's range never closes
+        self.completed_keys
+            .retain(|_, at| at.checked_add(window).is_none_or(|e| e > now));
+
+        if self.completed_keys.contains_key(key) {
+            return Err(PushError::Completed {
+                key: key.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn remember_key(&mut self, task: &Task<T, U, R, E, M>) {
+        if let Some(key) = &task.key {
+            if !self.dedup_window.is_zero() {
+                self.completed_keys.insert(key.clone(), self.clock.now());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::Schedule;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    fn keyed(data: i32, key: &str) -> Task<i32, ()> {
+        let mut task = Task::new(data);
+        task.set_key(key);
+        task
+    }
+
+    #[test]
+    fn test_pending() {
+        let (sender, events) = mpsc::channel();
+        let mut manager = TaskManager::<i32, ()>::new();
+
+        manager.subscribe(sender);
+
+        let first = manager.try_push(keyed(1, "job")).unwrap();
+        let duplicate = keyed(3, "job");
+        let duplicate_uuid = duplicate.uuid();
+
+        assert_eq!(
+            Err(PushError::Pending {
+                key: "job".to_string(),
+                uuid: first,
+            }),
+            manager.try_push(keyed(2, "job"))
+        );
+
+        manager.push(duplicate);
+        manager.push(keyed(4, "other"));
+        manager.push(Task::new(5));
+        manager.push(Task::new(6));
+
+        assert_eq!(4, manager.len());
+
+        // still pending while it runs
+        let (task, _) = manager.start_call().unwrap();
+
+        assert!(manager.try_push(keyed(7, "job")).is_err());
+
+        manager.finish_call(task, Ok(()));
+
+        // without a window the key is free again once the task is done
+        assert!(manager.try_push(keyed(8, "job")).is_ok());
+
+        // a popped task holds on to its key until it is handed back
+        let popped = manager.acquire().unwrap();
+
+        assert_eq!(Some("other"), popped.key());
+        assert!(manager.try_push(keyed(9, "other")).is_err());
+        assert!(manager.release(popped.uuid()));
+        assert!(manager.try_push(keyed(10, "other")).is_ok());
+
+        // inserting next to a queued task goes through the same checks
+        let anchor = manager.iter().next().unwrap().uuid();
+        let error = manager
+            .insert_after(anchor, keyed(11, "other"))
+            .unwrap_err();
+
+        assert!(matches!(error.rejected, Some(PushError::Pending { .. })));
+        assert_eq!(&11, error.task.data());
+
+        assert!(events
+            .try_iter()
+            .any(|e| e.uuid == duplicate_uuid && e.kind == EventKind::Rejected));
+    }
+
+    #[test]
+    fn test_window() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let mut manager = TaskManager::<i32, (), (), ()>::with_clock(clock.clone());
+
+        manager.set_dedup_window(Duration::from_secs(60));
+
+        let mut failing = keyed(1, "failing");
+        failing.set_callback(|_, _| Err(()));
+
+        manager.push(keyed(0, "job"));
+        manager.push(failing);
+        manager.call_all(&());
+
+        assert_eq!(
+            Err(PushError::Completed {
+                key: "job".to_string()
+            }),
+            manager.try_push(keyed(2, "job"))
+        );
+        // a failed job may be submitted again
+        assert!(manager.try_push(keyed(3, "failing")).is_ok());
+
+        clock.advance(Duration::from_secs(60));
+
+        assert!(manager.try_push(keyed(4, "job")).is_ok());
+
+        // a window too long to add to the clock never closes
+        manager.set_dedup_window(Duration::MAX);
+        manager.call_all(&());
+
+        assert!(manager.try_push(keyed(5, "job")).is_err());
+    }
+
+    #[test]
+    fn test_recurring() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let mut manager = TaskManager::<i32, ()>::with_clock(clock.clone());
+        let mut task = keyed(0, "tick");
+
+        manager.set_dedup_window(Duration::from_secs(60));
+        task.set_schedule(Some(Schedule::Every(Duration::from_secs(10))))
+            .unwrap();
+        manager.push(task);
+        clock.advance(Duration::from_secs(10));
+
+        assert!(manager.pop_and_call(()).unwrap().result.is_ok());
+        // its next run is queued even though the key just completed
+        assert_eq!(1, manager.len());
+
+        manager.cancel_all();
+
+        assert_eq!(
+            Err(PushError::Completed {
+                key: "tick".to_string()
+            }),
+            manager.try_push(keyed(1, "tick"))
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Holds, Local, Threading};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Queued,
+    Started,
+    Succeeded,
+    Failed,
+    RolledBack,
+    // the rollback failed, leaving the task applied
+    RollbackFailed,
+    Cancelled,
+    Retried,
+    // the deadline passed before the task ran
+    Expired,
+    // out of attempts, or a task it depends on did not succeed
+    DeadLettered,
+    // turned away by `push` as a duplicate, or while draining
+    Rejected,
+}
+
+// `attempt` is the number of times the task has been called so far, counting
+// the one that just started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub uuid: Uuid,
+    pub kind: EventKind,
+    pub timestamp: SystemTime,
+    pub attempt: u32,
+}
+
+pub trait Observer {
+    fn notify(&self, event: &Event);
+}
+
+impl<F> Observer for F
+where
+    F: Fn(&Event),
+{
+    fn notify(&self, event: &Event) {
+        self(event)
+    }
+}
+
+// Turns the events into a stream; they are dropped once the receiver is gone.
+impl Observer for Sender<Event> {
+    fn notify(&self, event: &Event) {
+        let _ = self.send(*event);
+    }
+}
+
+pub(crate) struct Observers<'a, M: Threading = Local> {
+    list: Vec<Arc<M::Observer<'a>>>,
+}
+
+impl<'a, M: Threading> Observers<'a, M> {
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn push<F>(&mut self, observer: F)
+    where
+        F: Observer + 'a,
+        M: Holds<F>,
+    {
+        self.list.push(M::observer(observer));
+    }
+
+    pub fn emit(&self, event: Event) {
+        for observer in &self.list {
+            observer.notify(&event);
+        }
+    }
+}
+
+impl<M: Threading> Default for Observers<'_, M> {
+    fn default() -> Self {
+        Self { list: Vec::new() }
+    }
+}
@@ -0,0 +1,190 @@
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+pub trait Executor {
+    fn block_on<F: Future>(&self, future: F) -> F::Output;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockingExecutor;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Executor for BlockingExecutor {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                return result;
+            }
+
+            thread::park();
+        }
+    }
+}
+
+// Polls at most `limit` futures at a time and returns their outputs in the
+// order the futures were given.
+pub async fn join_limited<F: Future>(futures: Vec<F>, limit: usize) -> Vec<F::Output> {
+    let limit = limit.max(1);
+    let len = futures.len();
+
+    let mut pending = futures.into_iter().map(Box::pin).collect::<Vec<_>>();
+    pending.reverse();
+
+    let mut running: Vec<(usize, Pin<Box<F>>)> = Vec::new();
+    let mut results = (0..len).map(|_| None).collect::<Vec<_>>();
+    let mut started = 0;
+
+    poll_fn(|cx| {
+        while running.len() < limit {
+            match pending.pop() {
+                Some(future) => {
+                    running.push((started, future));
+                    started += 1;
+                }
+                None => break,
+            }
+        }
+
+        let mut i = 0;
+        while i < running.len() {
+            let (index, future) = &mut running[i];
+
+            match future.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    results[*index] = Some(result);
+                    running.swap_remove(i);
+
+                    if let Some(future) = pending.pop() {
+                        running.push((started, future));
+                        started += 1;
+                    }
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        if running.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+// Sleeps on `clock` in a thread of its own, so that other futures polled
+// alongside keep going.
+pub(crate) async fn sleep(clock: Arc<dyn Clock>, duration: Duration) {
+    if duration.is_zero() {
+        return;
+    }
+
+    let state = Arc::new(Mutex::new((false, None::<Waker>)));
+    let shared = state.clone();
+
+    thread::spawn(move || {
+        clock.sleep(duration);
+
+        let mut state = shared.lock().unwrap();
+        state.0 = true;
+
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    });
+
+    poll_fn(|cx| {
+        let mut state = state.lock().unwrap();
+
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_block_on() {
+        let result = BlockingExecutor.block_on(async {
+            yield_now().await;
+            1 + 1
+        });
+
+        assert_eq!(2, result);
+    }
+
+    #[test]
+    fn test_join_limited() {
+        let in_flight = Cell::new(0);
+        let max_in_flight = Cell::new(0);
+
+        let futures = (0..5)
+            .map(|i| {
+                let in_flight = &in_flight;
+                let max_in_flight = &max_in_flight;
+
+                async move {
+                    in_flight.set(in_flight.get() + 1);
+                    max_in_flight.set(max_in_flight.get().max(in_flight.get()));
+
+                    for _ in 0..(5 - i) {
+                        yield_now().await;
+                    }
+
+                    in_flight.set(in_flight.get() - 1);
+                    i * 10
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let results = BlockingExecutor.block_on(join_limited(futures, 2));
+
+        assert_eq!(vec![0, 10, 20, 30, 40], results);
+        assert_eq!(2, max_in_flight.get());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    UnknownTask(Uuid),
+    Cycle(Vec<Uuid>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownTask(uuid) => write!(f, "unknown task: {}", uuid),
+            Self::Cycle(uuids) => {
+                let uuids = uuids.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+                write!(f, "dependency cycle: {}", uuids.join(" -> "))
+            }
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+pub(crate) struct Node<'a> {
+    pub uuid: Uuid,
+    pub dependencies: &'a [Uuid],
+    pub priority: i32,
+}
+
+// Kahn's algorithm, always taking the highest priority node and, among equal
+// priorities, the earliest queued one so that independent tasks keep their
+// FIFO order. Dependencies on UUIDs that are not part of `nodes` are treated
+// as already satisfied.
+pub(crate) fn topological_order(nodes: &[Node]) -> Result<Vec<Uuid>, GraphError> {
+    let indices = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.uuid, i))
+        .collect::<HashMap<_, _>>();
+
+    let mut placed = vec![false; nodes.len()];
+    let mut result = Vec::with_capacity(nodes.len());
+
+    while result.len() < nodes.len() {
+        let mut next: Option<usize> = None;
+
+        for (i, node) in nodes.iter().enumerate() {
+            let placeable = !placed[i]
+                && node
+                    .dependencies
+                    .iter()
+                    .all(|e| indices.get(e).is_none_or(|j| placed[*j]));
+
+            if placeable && next.is_none_or(|j| node.priority > nodes[j].priority) {
+                next = Some(i);
+            }
+        }
+
+        match next {
+            Some(i) => {
+                placed[i] = true;
+                result.push(nodes[i].uuid);
+            }
+            None => return Err(GraphError::Cycle(find_cycle(nodes, &indices, &placed))),
+        }
+    }
+
+    Ok(result)
+}
+
+// Every unplaced node has at least one unplaced dependency, so following them
+// from any unplaced node must eventually revisit a node.
+fn find_cycle(nodes: &[Node], indices: &HashMap<Uuid, usize>, placed: &[bool]) -> Vec<Uuid> {
+    let mut path: Vec<usize> = Vec::new();
+    let mut current = placed.iter().position(|e| !e).unwrap();
+
+    loop {
+        if let Some(start) = path.iter().position(|e| *e == current) {
+            let mut cycle = path[start..]
+                .iter()
+                .map(|i| nodes[*i].uuid)
+                .collect::<Vec<_>>();
+
+            cycle.reverse();
+
+            return cycle;
+        }
+
+        path.push(current);
+
+        current = nodes[current]
+            .dependencies
+            .iter()
+            .filter_map(|e| indices.get(e))
+            .copied()
+            .find(|i| !placed[*i])
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(uuid: Uuid, dependencies: &[Uuid], priority: i32) -> Node<'_> {
+        Node {
+            uuid,
+            dependencies,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        let unknown = [Uuid::new_v4()];
+        let on_c = [c];
+
+        let nodes = [node(a, &on_c, 0), node(b, &unknown, 0), node(c, &[], 0)];
+
+        assert_eq!(vec![b, c, a], topological_order(&nodes).unwrap());
+
+        let nodes = [node(a, &on_c, 0), node(b, &[], 0), node(c, &[], 1)];
+
+        assert_eq!(vec![c, a, b], topological_order(&nodes).unwrap());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        let d = Uuid::new_v4();
+
+        let (on_b, on_c) = ([b], [c]);
+
+        let nodes = [
+            node(a, &on_b, 0),
+            node(b, &on_c, 0),
+            node(c, &on_b, 0),
+            node(d, &[], 0),
+        ];
+
+        match topological_order(&nodes) {
+            Err(GraphError::Cycle(cycle)) => {
+                assert_eq!(2, cycle.len());
+                assert!(cycle.contains(&b));
+                assert!(cycle.contains(&c));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Operation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Started,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub uuid: Uuid,
+    pub operation: Operation,
+    pub phase: Phase,
+    pub timestamp: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "journal io error: {}", e),
+            Self::Json(e) => write!(f, "journal entry error: {}", e),
+        }
+    }
+}
+
+impl Error for JournalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for JournalError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for JournalError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+// An append-only file of JSON lines, synced to disk after every entry.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    // A final line cut short by a crash is dropped, so new entries start on a
+    // line of their own.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let content = fs::read(&path)?;
+
+        if content.last().is_some_and(|e| *e != b'\n') {
+            let end = content
+                .iter()
+                .rposition(|e| *e == b'\n')
+                .map_or(0, |e| e + 1);
+
+            file.set_len(end as u64)?;
+        }
+
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<JournalEntry>, JournalError> {
+        let content = fs::read_to_string(&self.path)?;
+
+        Ok(content
+            .lines()
+            .filter(|e| !e.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    // Tasks whose last execute started but never finished, most recently
+    // started first.
+    pub fn in_doubt(&self) -> Result<Vec<Uuid>, JournalError> {
+        self.unfinished(Operation::Execute)
+    }
+
+    // Tasks whose last rollback started but never finished. Running such a
+    // rollback again could undo more than the task ever did, so these are
+    // left for the caller to look into.
+    pub fn rollbacks_in_doubt(&self) -> Result<Vec<Uuid>, JournalError> {
+        self.unfinished(Operation::Rollback)
+    }
+
+    fn unfinished(&self, operation: Operation) -> Result<Vec<Uuid>, JournalError> {
+        let mut last = HashMap::new();
+
+        for (i, entry) in self.entries()?.into_iter().enumerate() {
+            last.insert(entry.uuid, (i, entry));
+        }
+
+        let mut result = last
+            .into_values()
+            .filter(|(_, e)| e.operation == operation && e.phase == Phase::Started)
+            .map(|(i, e)| (i, e.uuid))
+            .collect::<Vec<_>>();
+
+        result.sort_by_key(|e| Reverse(e.0));
+
+        Ok(result.into_iter().map(|(_, uuid)| uuid).collect())
+    }
+
+    pub fn clear(&mut self) -> Result<(), JournalError> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+// `missing` lists tasks in doubt that the invoker does not hold any more, and
+// `rollbacks_in_doubt` the rollbacks a crash cut short, which are not retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovery<R, E> {
+    pub rolled_back: Vec<(Uuid, Result<R, E>)>,
+    pub missing: Vec<Uuid>,
+    pub rollbacks_in_doubt: Vec<Uuid>,
+}
+
+impl<R, E> Recovery<R, E> {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+            && self.rollbacks_in_doubt.is_empty()
+            && self.rolled_back.iter().all(|(_, e)| e.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_in_doubt() {
+        let path = env::temp_dir().join(format!("journal-{}.jsonl", Uuid::new_v4()));
+        let mut journal = Journal::open(&path).unwrap();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let mut append = |uuid, operation, phase| {
+            journal
+                .append(&JournalEntry {
+                    uuid,
+                    operation,
+                    phase,
+                    timestamp: SystemTime::UNIX_EPOCH,
+                })
+                .unwrap();
+        };
+
+        append(a, Operation::Execute, Phase::Started);
+        append(a, Operation::Execute, Phase::Completed);
+        append(b, Operation::Execute, Phase::Started);
+        append(c, Operation::Execute, Phase::Started);
+        append(c, Operation::Execute, Phase::Failed);
+        append(a, Operation::Rollback, Phase::Started);
+
+        // a crash in the middle of writing an entry
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"uuid":"#)
+            .unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+
+        assert_eq!(6, journal.entries().unwrap().len());
+        // the rollback of a is not in doubt as an execute
+        assert_eq!(vec![b], journal.in_doubt().unwrap());
+        assert_eq!(vec![a], journal.rollbacks_in_doubt().unwrap());
+
+        journal.clear().unwrap();
+
+        assert!(journal.entries().unwrap().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+}
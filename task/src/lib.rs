@@ -1,34 +1,137 @@
-mod task;
+mod async_task;
+mod cancel;
+pub mod clock;
+mod composite;
+mod dedup;
+mod event;
+pub mod executor;
+mod graph;
+mod journal;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod middleware;
+mod persist;
+mod plan;
+mod queues;
+mod quota;
+mod results;
+mod retry;
+mod schedule;
+mod shared;
+pub mod task;
+mod threading;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use uuid::Uuid;
 
-pub struct Task<T, U> {
+use crate::clock::{Clock, SystemClock};
+use crate::event::Observers;
+
+pub use crate::async_task::AsyncTask;
+pub use crate::cancel::{CancellationToken, Interrupt};
+pub use crate::composite::{Conditional, Group, Sequence};
+pub use crate::dedup::PushError;
+pub use crate::event::{Event, EventKind, Observer};
+pub use crate::graph::GraphError;
+pub use crate::journal::{Journal, JournalEntry, JournalError, Phase, Recovery};
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{Metrics, Timing};
+pub use crate::middleware::{Chain, Hooks, Invocation, Middleware, Operation};
+pub use crate::persist::{PersistError, QueueSnapshot, TaskRegistry, TaskSnapshot};
+pub use crate::plan::{Outcome, Plan, PlanEntry};
+pub use crate::queues::{QueueError, QueueRegistry};
+pub use crate::quota::Quota;
+pub use crate::retry::{Backoff, RetryPolicy};
+pub use crate::schedule::{Cron, CronError, Schedule, ScheduleError};
+pub use crate::shared::{SharedTaskManager, WorkerPool};
+pub use crate::task::{Command, FnCommand, Identified, Invoker, TransactionReport};
+pub use crate::threading::{Holds, Local, Shared, Threading};
+
+type Callback<T, U, R, E, M> = Arc<<M as Threading>::Callback<T, U, R, E>>;
+type DryRunCallback<T, U, R, E, M> = Box<<M as Threading>::DryRun<T, U, R, E>>;
+type AsyncCallback<T, U, R, E, M> = Arc<<M as Threading>::AsyncCallback<T, U, R, E>>;
+// a task taken off the queue, with the invocation it is about to be called in
+type Started<T, U, R, E, M> = (Task<T, U, R, E, M>, Invocation);
+
+pub struct Task<T, U, R = (), E = (), M: Threading = Local> {
     uuid: Uuid,
     before: Option<Uuid>,
     after: Option<Uuid>,
+    dependencies: Vec<Uuid>,
+    kind: Option<String>,
+    quota_group: Option<String>,
+    key: Option<String>,
+    retry_policy: Option<RetryPolicy<E>>,
+    not_before: Option<SystemTime>,
+    priority: i32,
+    deadline: Option<SystemTime>,
+    timeout: Option<Duration>,
+    token: CancellationToken,
+    schedule: Option<Schedule>,
+    previous_run: Option<Uuid>,
+    suspended: bool,
+    source: Option<Uuid>,
+    input: Option<R>,
     data: T,
 
-    do_callback: Arc<dyn Fn(&mut Self, &U) -> bool>,
-    do_dry_run: Box<dyn Fn(&Self, &U) -> bool>,
+    do_callback: Callback<T, U, R, E, M>,
+    do_dry_run: DryRunCallback<T, U, R, E, M>,
+    do_rollback: Callback<T, U, R, E, M>,
+    do_async_callback: Option<AsyncCallback<T, U, R, E, M>>,
     called: u32,
 }
 
-impl<T, U> Task<T, U> {
+impl<T, U, R, E> Task<T, U, R, E>
+where
+    R: Default,
+{
     pub fn new(data: T) -> Self {
+        Self::new_in(data, Local)
+    }
+}
+
+impl<T, U, R, E, M: Threading> Task<T, U, R, E, M>
+where
+    R: Default,
+{
+    // A `Task<_, _, _, _, Shared>` only takes `Send + Sync` callbacks, so it
+    // can be handed to a `SharedTaskManager`.
+    pub fn new_in(data: T, _threading: M) -> Self {
         Self {
             uuid: Uuid::new_v4(),
             before: None,
             after: None,
+            dependencies: Vec::new(),
+            kind: None,
+            quota_group: None,
+            key: None,
+            retry_policy: None,
+            not_before: None,
+            priority: 0,
+            deadline: None,
+            timeout: None,
+            token: CancellationToken::new(),
+            schedule: None,
+            previous_run: None,
+            suspended: false,
+            source: None,
+            input: None,
             data,
-            do_callback: Arc::new(|_, _| true),
-            do_dry_run: Box::new(|_, _| true),
+            do_callback: M::default_callback(),
+            do_dry_run: M::default_dry_run(),
+            do_rollback: M::default_callback(),
+            do_async_callback: None,
             called: 0,
         }
     }
+}
 
+impl<T, U, R, E, M: Threading> Task<T, U, R, E, M> {
     pub fn uuid(&self) -> Uuid {
         self.uuid
     }
@@ -37,26 +140,185 @@ impl<T, U> Task<T, U> {
         &self.data
     }
 
-    pub fn call(&mut self, data: U) -> bool {
+    pub fn call(&mut self, data: U) -> Result<R, E> {
+        self.call_ref(&data)
+    }
+
+    fn call_ref(&mut self, data: &U) -> Result<R, E> {
         self.called += 1;
         let cb = ((self as &mut Self).do_callback).clone();
-        cb(self, &data)
+        cb(self, data)
+    }
+
+    // Swaps `data` in for the task's own for the length of `f`.
+    fn with_data<X>(&mut self, data: &mut T, f: impl FnOnce(&mut Self) -> X) -> X {
+        std::mem::swap(&mut self.data, data);
+        let result = f(self);
+        std::mem::swap(&mut self.data, data);
+
+        result
     }
 
     pub fn dry_run(&self, data: U) -> bool {
         ((self as &Self).do_dry_run)(self, &data)
     }
 
+    pub fn rollback(&mut self, data: U) -> Result<R, E> {
+        let cb = self.do_rollback.clone();
+        cb(self, &data)
+    }
+
     pub fn called(&self) -> u32 {
         self.called
     }
 
-    pub fn set_callback(&mut self, callback: impl Fn(&mut Self, &U) -> bool + 'static) {
-        self.do_callback = Arc::new(callback);
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    pub fn set_kind(&mut self, kind: impl Into<String>) {
+        self.kind = Some(kind.into());
+    }
+
+    // The `Quota` the task is held to, if any.
+    pub fn quota_group(&self) -> Option<&str> {
+        self.quota_group.as_deref()
+    }
+
+    pub fn set_quota_group(&mut self, group: impl Into<String>) {
+        self.quota_group = Some(group.into());
+    }
+
+    // Tasks sharing an idempotency key are the same logical job, queued at
+    // most once.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    pub fn set_key(&mut self, key: impl Into<String>) {
+        self.key = Some(key.into());
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy<E>> {
+        self.retry_policy.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy<E>>) {
+        self.retry_policy = policy;
+    }
+
+    pub fn not_before(&self) -> Option<SystemTime> {
+        self.not_before
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: i32) {
+        self.priority = priority;
+    }
+
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<SystemTime>) {
+        self.deadline = deadline;
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    // Callbacks see the timeout through `token()`; a task that ignores it is
+    // still reported as timed out once it returns.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    pub fn set_schedule(&mut self, schedule: Option<Schedule>) -> Result<(), ScheduleError> {
+        if let Some(schedule) = &schedule {
+            schedule.validate()?;
+        }
+
+        self.schedule = schedule;
+
+        Ok(())
+    }
+
+    // The run of a recurring task this one was scheduled after.
+    pub fn previous_run(&self) -> Option<Uuid> {
+        self.previous_run
+    }
+
+    // A suspended task keeps its place in the queue but is skipped when
+    // popping, and so are the tasks depending on it.
+    pub fn suspend(&mut self) {
+        self.suspended = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.suspended = false;
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
     }
 
-    pub fn set_dry_run_callback(&mut self, callback: impl Fn(&Self, &U) -> bool + 'static) {
-        self.do_dry_run = Box::new(callback);
+    // The task whose output this one gets as input; see `TaskManager::pipe`.
+    pub fn source(&self) -> Option<Uuid> {
+        self.source
+    }
+
+    pub fn set_source(&mut self, source: Option<Uuid>) {
+        self.source = source;
+    }
+
+    // The output of the source task, handed over by the manager right before
+    // the call.
+    pub fn input(&self) -> Option<&R> {
+        self.input.as_ref()
+    }
+
+    pub fn take_input(&mut self) -> Option<R> {
+        self.input.take()
+    }
+
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Self, &U) -> Result<R, E> + 'static,
+        M: Holds<F>,
+    {
+        self.do_callback = M::callback(callback);
+    }
+
+    pub fn set_dry_run_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&Self, &U) -> bool + 'static,
+        M: Holds<F>,
+    {
+        self.do_dry_run = M::dry_run(callback);
+    }
+
+    pub fn set_rollback_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Self, &U) -> Result<R, E> + 'static,
+        M: Holds<F>,
+    {
+        self.do_rollback = M::callback(callback);
     }
 
     pub fn update_before(&mut self, uuid: Option<Uuid>) -> Option<Uuid> {
@@ -74,145 +336,1875 @@ impl<T, U> Task<T, U> {
 
         result
     }
+
+    pub fn dependencies(&self) -> &[Uuid] {
+        &self.dependencies
+    }
+
+    // Unchecked for cycles; callers outside the crate go through
+    // `TaskManager::add_dependency`.
+    pub(crate) fn add_dependency(&mut self, uuid: Uuid) -> bool {
+        if uuid == self.uuid || self.dependencies.contains(&uuid) {
+            return false;
+        }
+
+        self.dependencies.push(uuid);
+
+        true
+    }
+
+    // What has to finish before the task can run: its dependencies and the
+    // task it gets its input from.
+    fn prerequisites(&self) -> impl Iterator<Item = &Uuid> {
+        self.dependencies.iter().chain(&self.source)
+    }
+
+    pub fn remove_dependency(&mut self, uuid: Uuid) -> bool {
+        let len = self.dependencies.len();
+
+        self.dependencies.retain(|e| *e != uuid);
+
+        len != self.dependencies.len()
+    }
+}
+
+// Under an invoker the invoker's data stands in for the task's own, so the
+// callbacks read and change it through `this.data`.
+impl<T, U, R, E, M: Threading> task::Command<T, U, R, E> for Task<T, U, R, E, M> {
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.with_data(data, |this| this.call_ref(arg))
+    }
+
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.with_data(data, |this| {
+            let cb = this.do_rollback.clone();
+            cb(this, arg)
+        })
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        self.with_data(data, |this| ((this as &Self).do_dry_run)(this, arg))
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    // The invoker's token stands in for the task's own while it runs, so
+    // callbacks polling `token()` see the invoker's cancellation and timeout.
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        let own = std::mem::replace(&mut self.token, token.clone());
+        let result = self.with_data(data, |this| this.call_ref(arg));
+
+        self.token = own;
+
+        result
+    }
 }
 
-pub struct TaskManager<T, U> {
-    tasks: VecDeque<Task<T, U>>,
+// `task` is `None` when the manager kept the task, to retry it, to run it again
+// on its schedule or as a dead letter.
+pub struct CallOutcome<T, U, R = (), E = (), M: Threading = Local> {
+    pub uuid: Uuid,
+    pub result: Result<R, E>,
+    pub interrupt: Option<Interrupt>,
+    pub task: Option<Task<T, U, R, E, M>>,
 }
 
-impl<T, U> TaskManager<T, U> {
-    pub fn new() -> Self {
-        Self {
-            tasks: VecDeque::new(),
+// A task `insert_before` or `insert_after` could not place, as the task it
+// was to go next to is not queued, or as `try_push` would have turned it away.
+pub struct InsertError<T, U, R = (), E = (), M: Threading = Local> {
+    pub anchor: Uuid,
+    pub task: Box<Task<T, U, R, E, M>>,
+    pub rejected: Option<PushError>,
+}
+
+impl<T, U, R, E, M: Threading> fmt::Debug for InsertError<T, U, R, E, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InsertError")
+            .field("anchor", &self.anchor)
+            .field("task", &self.task.uuid)
+            .field("rejected", &self.rejected)
+            .finish()
+    }
+}
+
+impl<T, U, R, E, M: Threading> fmt::Display for InsertError<T, U, R, E, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.rejected {
+            Some(e) => write!(f, "task to insert was rejected: {}", e),
+            None => write!(f, "task to insert next to is not queued: {}", self.anchor),
         }
     }
+}
+
+impl<T, U, R, E, M: Threading> Error for InsertError<T, U, R, E, M> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.rejected.as_ref().map(|e| e as _)
+    }
+}
+
+pub struct Summary<R = (), E = ()> {
+    pub outcomes: Vec<(Uuid, Result<R, E>)>,
+}
+
+impl<R, E> Summary<R, E> {
+    pub fn len(&self) -> usize {
+        self.outcomes.len()
+    }
 
     pub fn is_empty(&self) -> bool {
-        self.tasks.is_empty()
+        self.outcomes.is_empty()
     }
 
-    pub fn clear(&mut self) {
-        self.tasks.clear();
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(|(_, e)| e.is_ok())
     }
 
-    pub fn len(&self) -> usize {
-        self.tasks.len()
+    pub fn succeeded(&self) -> impl Iterator<Item = (&Uuid, &R)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(uuid, result)| result.as_ref().ok().map(|e| (uuid, e)))
     }
 
-    pub fn push(&mut self, mut task: Task<T, U>) {
-        if !self.is_empty() {
-            let last = self.tasks.back_mut().unwrap();
+    pub fn failed(&self) -> impl Iterator<Item = (&Uuid, &E)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(uuid, result)| result.as_ref().err().map(|e| (uuid, e)))
+    }
+}
 
-            last.update_after(Some(task.uuid()));
-            task.update_before(Some(last.uuid()));
+// What the manager keeps about a task while it is being called.
+struct Running {
+    token: CancellationToken,
+    quota_group: Option<String>,
+    key: Option<String>,
+    source: Option<Uuid>,
+    // taken with `acquire`, so called by whoever took it
+    popped: bool,
+}
+
+impl Running {
+    fn of<T, U, R, E, M: Threading>(task: &Task<T, U, R, E, M>) -> Self {
+        Self {
+            token: task.token.clone(),
+            quota_group: task.quota_group.clone(),
+            key: task.key.clone(),
+            source: task.source,
+            popped: false,
         }
+    }
+}
 
-        self.tasks.push_back(task);
+pub struct TaskManager<T, U, R = (), E = (), M: Threading = Local> {
+    tasks: VecDeque<Task<T, U, R, E, M>>,
+    dead_letters: Vec<Task<T, U, R, E, M>>,
+    cancelled: Vec<Task<T, U, R, E, M>>,
+    expired: Vec<Task<T, U, R, E, M>>,
+    running: HashMap<Uuid, Running>,
+    // tasks that left the queue without succeeding, for as long as a task
+    // depending on them, which can never run, is queued or running
+    failed: HashSet<Uuid>,
+    quotas: HashMap<String, Quota>,
+    results: HashMap<Uuid, R>,
+    // set once results are kept or piped
+    clone_result: Option<fn(&R) -> R>,
+    // every result is kept, not only the ones a queued task reads
+    keep_results: bool,
+    // keys of tasks that succeeded, with when they did
+    completed_keys: HashMap<String, SystemTime>,
+    dedup_window: Duration,
+    paused: bool,
+    draining: bool,
+    retry_policy: Option<RetryPolicy<E>>,
+    middleware: Chain<'static, Result<R, E>, M>,
+    observers: Observers<'static, M>,
+    // gauges kept current, see `add_metrics`
+    #[cfg(feature = "metrics")]
+    metrics: Vec<Metrics>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<T, U, R, E> TaskManager<T, U, R, E> {
+    pub fn new() -> Self {
+        Self::new_in(Local)
     }
 
-    pub fn pop(&mut self) -> Option<Task<T, U>> {
-        let result = self.tasks.pop_front();
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::with_clock_in(clock, Local)
+    }
+}
 
-        if !self.is_empty() {
-            let first = self.tasks.front_mut().unwrap();
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    // A `TaskManager<_, _, _, _, Shared>` only takes `Send + Sync` tasks,
+    // middleware and observers, so it can be wrapped in a `SharedTaskManager`.
+    pub fn new_in(_threading: M) -> Self {
+        Self {
+            tasks: VecDeque::new(),
+            dead_letters: Vec::new(),
+            cancelled: Vec::new(),
+            expired: Vec::new(),
+            running: HashMap::new(),
+            failed: HashSet::new(),
+            quotas: HashMap::new(),
+            results: HashMap::new(),
+            clone_result: None,
+            keep_results: false,
+            completed_keys: HashMap::new(),
+            dedup_window: Duration::ZERO,
+            paused: false,
+            draining: false,
+            retry_policy: None,
+            middleware: Chain::default(),
+            observers: Observers::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Vec::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
 
-            first.update_before(None);
+    pub fn with_clock_in(clock: Arc<dyn Clock>, threading: M) -> Self {
+        Self {
+            clock,
+            ..Self::new_in(threading)
         }
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy<E>> {
+        self.retry_policy.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy<E>>) {
+        self.retry_policy = policy;
+    }
+
+    pub fn middleware(&self) -> &Chain<'static, Result<R, E>, M> {
+        &self.middleware
+    }
+
+    pub fn add_middleware<X>(&mut self, middleware: X)
+    where
+        X: Middleware<Result<R, E>> + 'static,
+        M: Holds<X>,
+    {
+        self.middleware.push(middleware);
+    }
+
+    pub fn subscribe<X>(&mut self, observer: X)
+    where
+        X: Observer + 'static,
+        M: Holds<X>,
+    {
+        self.observers.push(observer);
+    }
+
+    pub fn dead_letters(&self) -> &[Task<T, U, R, E, M>] {
+        &self.dead_letters
+    }
+
+    pub fn take_dead_letters(&mut self) -> Vec<Task<T, U, R, E, M>> {
+        let result = std::mem::take(&mut self.dead_letters);
+
+        self.update_gauges();
 
         result
     }
 
-    pub fn pop_and_call(&mut self, data: U) -> (Option<Task<T, U>>, bool) {
-        let mut result = self.pop();
+    pub fn cancelled(&self) -> &[Task<T, U, R, E, M>] {
+        &self.cancelled
+    }
 
-        let called = if let Some(task) = result.as_mut() {
-            task.call(data)
-        } else {
-            false
-        };
+    pub fn take_cancelled(&mut self) -> Vec<Task<T, U, R, E, M>> {
+        let result = std::mem::take(&mut self.cancelled);
+
+        self.update_gauges();
 
-        (result, called)
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // A queued task moves to the cancelled list, while a running one only has
+    // its token cancelled.
+    pub fn cancel(&mut self, uuid: Uuid) -> bool {
+        if let Some(task) = self.remove(uuid) {
+            task.token.cancel();
+            self.emit(uuid, EventKind::Cancelled, task.called);
+            self.failed.insert(uuid);
+            self.cancelled.push(task);
+            self.update_gauges();
 
-    #[test]
-    fn test_uuid() {
-        let task1 = Task::<_, i32>::new(1);
-        let task2 = Task::<_, i32>::new(1);
+            return true;
+        }
 
-        assert_ne!(task1.uuid, task2.uuid);
+        match self.running.get(&uuid) {
+            Some(running) => {
+                running.token.cancel();
+                true
+            }
+            None => false,
+        }
     }
 
-    #[test]
-    fn test_len() {
-        let mut manager = TaskManager::new();
+    pub fn cancel_all(&mut self) -> usize {
+        let len = self.tasks.len() + self.running.len();
 
-        assert_eq!(0, manager.len());
+        for task in std::mem::take(&mut self.tasks) {
+            task.token.cancel();
+            self.emit(task.uuid, EventKind::Cancelled, task.called);
+            self.failed.insert(task.uuid);
+            self.cancelled.push(task);
+        }
 
-        manager.push(Task::new(1));
-        manager.push(Task::new(2));
-        manager.push(Task::new(3));
-        manager.push(Task::new(4));
+        for running in self.running.values() {
+            running.token.cancel();
+        }
 
-        assert_eq!(4, manager.len());
+        self.update_gauges();
 
-        manager.pop();
+        len
+    }
 
-        assert_eq!(3, manager.len());
+    pub fn is_running(&self, uuid: Uuid) -> bool {
+        self.running.contains_key(&uuid)
+    }
 
-        manager.clear();
+    pub fn expired(&self) -> &[Task<T, U, R, E, M>] {
+        &self.expired
+    }
 
-        assert_eq!(0, manager.len());
+    pub fn take_expired(&mut self) -> Vec<Task<T, U, R, E, M>> {
+        let result = std::mem::take(&mut self.expired);
 
-        manager.pop_and_call(1);
-        assert_eq!(0, manager.len());
+        self.update_gauges();
+
+        result
     }
 
-    #[test]
-    fn test_call() {
-        let mut task = Task::new(1);
+    // Moves every queued task whose deadline has passed to the expired list.
+    pub fn expire(&mut self) -> usize {
+        let now = self.clock.now();
+        let len = self.expired.len();
 
-        assert_eq!(0, task.called());
-        assert_eq!(&1, task.data());
+        let mut i = 0;
+        while i < self.tasks.len() {
+            if self.tasks[i].deadline.is_some_and(|e| e < now) {
+                let task = self.tasks.remove(i).unwrap();
 
-        assert_eq!(true, task.call(1));
+                self.emit(task.uuid, EventKind::Expired, task.called);
+                self.failed.insert(task.uuid);
+                self.expired.push(task);
+            } else {
+                i += 1;
+            }
+        }
 
-        assert_eq!(1, task.called());
+        if self.expired.len() != len {
+            self.relink();
+            self.update_gauges();
+        }
 
-        task.set_callback(|this, arg| {
-            assert_eq!(2, *arg);
+        self.expired.len() - len
+    }
 
-            this.data = 3;
+    pub fn set_priority(&mut self, uuid: Uuid, priority: i32) -> bool {
+        match self.position(uuid) {
+            Some(index) => {
+                self.tasks[index].set_priority(priority);
+                true
+            }
+            None => false,
+        }
+    }
 
-            false
-        });
+    pub fn next_due(&self) -> Option<SystemTime> {
+        self.tasks.iter().filter_map(|e| e.not_before).min()
+    }
 
-        assert_eq!(false, task.call(2));
-        assert_eq!(&3, task.data());
-        assert_eq!(1, Arc::strong_count(&task.do_callback));
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
     }
 
-    #[test]
-    fn test_dry_run() {
-        let mut task = Task::new(1);
+    pub fn clear(&mut self) {
+        self.tasks.clear();
+        self.update_gauges();
+    }
 
-        assert_eq!(0, task.called());
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
 
-        assert_eq!(true, task.dry_run(1));
+    pub fn contains(&self, uuid: Uuid) -> bool {
+        self.position(uuid).is_some()
+    }
 
-        assert_eq!(0, task.called());
+    // A duplicate of a pending or recently completed task is dropped, merged
+    // into the one submitted first, and so is any task while draining; both
+    // are reported as `Rejected` events, and `try_push` returns them as errors.
+    pub fn push(&mut self, task: Task<T, U, R, E, M>) {
+        let (uuid, called) = (task.uuid, task.called);
 
-        task.set_dry_run_callback(|this, arg| {
-            assert_eq!(2, *arg);
+        if self.try_push(task).is_err() {
+            self.emit(uuid, EventKind::Rejected, called);
+        }
+    }
 
-            false
-        });
+    fn enqueue(&mut self, mut task: Task<T, U, R, E, M>) {
+        self.schedule_first(&mut task);
 
-        assert_eq!(false, task.dry_run(2));
-        assert_eq!(1, Arc::strong_count(&task.do_callback));
+        if !self.is_empty() {
+            let last = self.tasks.back_mut().unwrap();
+
+            last.update_after(Some(task.uuid()));
+            task.update_before(Some(last.uuid()));
+        }
+
+        self.tasks.push_back(task);
+    }
+
+    pub fn get(&self, uuid: Uuid) -> Option<&Task<T, U, R, E, M>> {
+        self.tasks.get(self.position(uuid)?)
+    }
+
+    pub fn get_mut(&mut self, uuid: Uuid) -> Option<&mut Task<T, U, R, E, M>> {
+        let index = self.position(uuid)?;
+
+        self.tasks.get_mut(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Task<T, U, R, E, M>> {
+        self.tasks.iter()
+    }
+
+    pub fn remove(&mut self, uuid: Uuid) -> Option<Task<T, U, R, E, M>> {
+        let result = self.tasks.remove(self.position(uuid)?);
+
+        self.relink();
+        self.update_gauges();
+
+        result
+    }
+
+    // The task is handed back in the error if `uuid` is not queued, or if
+    // `try_push` would reject it.
+    pub fn insert_before(
+        &mut self,
+        uuid: Uuid,
+        task: Task<T, U, R, E, M>,
+    ) -> Result<(), InsertError<T, U, R, E, M>> {
+        let Some(index) = self.position(uuid) else {
+            return Err(InsertError {
+                anchor: uuid,
+                task: Box::new(task),
+                rejected: None,
+            });
+        };
+
+        if let Err(e) = self.check_push(&task) {
+            return Err(InsertError {
+                anchor: uuid,
+                task: Box::new(task),
+                rejected: Some(e),
+            });
+        }
+
+        self.emit(task.uuid, EventKind::Queued, task.called);
+        self.insert(index, task);
+
+        Ok(())
+    }
+
+    pub fn insert_after(
+        &mut self,
+        uuid: Uuid,
+        task: Task<T, U, R, E, M>,
+    ) -> Result<(), InsertError<T, U, R, E, M>> {
+        let Some(index) = self.position(uuid) else {
+            return Err(InsertError {
+                anchor: uuid,
+                task: Box::new(task),
+                rejected: None,
+            });
+        };
+
+        if let Err(e) = self.check_push(&task) {
+            return Err(InsertError {
+                anchor: uuid,
+                task: Box::new(task),
+                rejected: Some(e),
+            });
+        }
+
+        self.emit(task.uuid, EventKind::Queued, task.called);
+        self.insert(index + 1, task);
+
+        Ok(())
+    }
+
+    // Indices past the back of the queue move the task to the back.
+    pub fn move_to(&mut self, uuid: Uuid, index: usize) -> bool {
+        let Some(task) = self.position(uuid).and_then(|e| self.tasks.remove(e)) else {
+            return false;
+        };
+
+        self.insert(index.min(self.len()), task);
+
+        true
+    }
+
+    // Takes the highest priority ready task within its quota, the earliest
+    // queued one among equal priorities, and hands it over for good: its
+    // dependents may run right away, while a task piped its output never gets
+    // it, so goes to the dead letters.
+    pub fn pop(&mut self) -> Option<Task<T, U, R, E, M>> {
+        let mut task = self.take_next()?;
+
+        self.attach_input(&mut task);
+
+        if self.reads(task.uuid) {
+            self.failed.insert(task.uuid);
+        }
+
+        self.update_gauges();
+
+        Some(task)
+    }
+
+    // Like `pop`, but the task counts as running until it is handed back
+    // through `finish` or `release`: it holds its quota slot and key, and a
+    // task piped its output waits for it.
+    pub fn acquire(&mut self) -> Option<Task<T, U, R, E, M>> {
+        let mut task = self.take_next()?;
+
+        self.attach_input(&mut task);
+
+        self.running.insert(
+            task.uuid,
+            Running {
+                popped: true,
+                ..Running::of(&task)
+            },
+        );
+        self.update_gauges();
+
+        Some(task)
+    }
+
+    // For a task taken with `acquire`, with the result of calling it: it is
+    // retried, scheduled again or dead-lettered like one the manager called.
+    pub fn finish(
+        &mut self,
+        task: Task<T, U, R, E, M>,
+        result: Result<R, E>,
+    ) -> CallOutcome<T, U, R, E, M> {
+        self.finish_call(task, result)
+    }
+
+    // Gives up on a task taken with `acquire` without reporting an outcome. A
+    // task piped its output will never get it, so goes to the dead letters.
+    pub fn release(&mut self, uuid: Uuid) -> bool {
+        if !self.running.get(&uuid).is_some_and(|e| e.popped) {
+            return false;
+        }
+
+        if self.reads(uuid) {
+            self.failed.insert(uuid);
+        }
+
+        self.running.remove(&uuid);
+        self.update_gauges();
+
+        true
+    }
+
+    fn take_next(&mut self) -> Option<Task<T, U, R, E, M>> {
+        let index = self.next_ready()?;
+        let result = self.tasks.remove(index)?;
+
+        self.take_quota(&result);
+        self.relink();
+
+        Some(result)
+    }
+
+    // Where the task `pop` takes next is queued.
+    pub(crate) fn next_ready(&mut self) -> Option<usize> {
+        self.expire();
+        self.sweep_cancelled();
+        self.sweep_dependents();
+        self.release_results();
+        self.refill_quotas();
+
+        if self.paused {
+            return None;
+        }
+
+        let mut index: Option<usize> = None;
+
+        for (i, task) in self.tasks.iter().enumerate() {
+            if self.is_ready(task)
+                && self.within_quota(task)
+                && index.is_none_or(|j| task.priority > self.tasks[j].priority)
+            {
+                index = Some(i);
+            }
+        }
+
+        index
+    }
+
+    // A failed task that may be retried is queued again and a task that ran out
+    // of attempts is moved to the dead letters; its outcome is reported either
+    // way.
+    pub fn pop_and_call(&mut self, data: U) -> Option<CallOutcome<T, U, R, E, M>> {
+        self.call_next(&data)
+    }
+
+    pub fn call_all(&mut self, data: &U) -> Summary<R, E> {
+        let mut summary = Summary {
+            outcomes: Vec::new(),
+        };
+
+        while let Some(outcome) = self.call_next(data) {
+            summary.outcomes.push((outcome.uuid, outcome.result));
+        }
+
+        summary
+    }
+
+    // Calls tasks as they become due until `until`, sleeping on the clock in
+    // between; with a `ManualClock` this runs without actually waiting.
+    pub fn run_until(&mut self, data: &U, until: SystemTime) -> Summary<R, E> {
+        let mut summary = Summary {
+            outcomes: Vec::new(),
+        };
+
+        loop {
+            while let Some(outcome) = self.call_next(data) {
+                summary.outcomes.push((outcome.uuid, outcome.result));
+            }
+
+            let now = self.clock.now();
+
+            match self.next_wake() {
+                Some(wake) if wake <= until => self
+                    .clock
+                    .sleep(wake.duration_since(now).unwrap_or_default()),
+                _ => break,
+            }
+        }
+
+        summary
+    }
+
+    // Nothing is popped until `resume`; tasks already running finish as usual.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    fn start_drain(&mut self) {
+        self.draining = true;
+        self.paused = false;
+    }
+
+    // Takes new tasks again after a drain.
+    pub fn end_drain(&mut self) {
+        self.draining = false;
+    }
+
+    // Suspends a queued task; see `Task::suspend`.
+    pub fn suspend(&mut self, uuid: Uuid) -> bool {
+        match self.position(uuid) {
+            Some(index) => {
+                self.tasks[index].suspend();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn resume_task(&mut self, uuid: Uuid) -> bool {
+        match self.position(uuid) {
+            Some(index) => {
+                self.tasks[index].resume();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Stops taking new tasks until `end_drain`, resumes a paused queue and
+    // calls everything queued, waiting on the clock for tasks that are not
+    // due yet. Recurring tasks are not scheduled again; suspended tasks and
+    // their dependents are left in the queue.
+    pub fn drain(&mut self, data: &U) -> Summary<R, E> {
+        let mut summary = Summary {
+            outcomes: Vec::new(),
+        };
+
+        self.start_drain();
+
+        loop {
+            while let Some(outcome) = self.call_next(data) {
+                summary.outcomes.push((outcome.uuid, outcome.result));
+            }
+
+            let now = self.clock.now();
+
+            match self.next_wake() {
+                Some(wake) => self
+                    .clock
+                    .sleep(wake.duration_since(now).unwrap_or_default()),
+                None => break,
+            }
+        }
+
+        summary
+    }
+
+    fn call_next(&mut self, data: &U) -> Option<CallOutcome<T, U, R, E, M>> {
+        let (mut task, invocation) = self.start_call()?;
+        let result = self.middleware.run(&invocation, || task.call_ref(data));
+
+        Some(self.finish_call(task, result))
+    }
+
+    // The task counts as running, holding back its dependents and reachable
+    // through `cancel`, until `finish_call`.
+    fn start_call(&mut self) -> Option<Started<T, U, R, E, M>> {
+        let mut task = self.take_next()?;
+
+        self.attach_input(&mut task);
+
+        let invocation = Invocation {
+            uuid: task.uuid(),
+            operation: Operation::Call,
+            attempt: task.called() + 1,
+        };
+
+        task.token.set_timeout(&self.clock, task.timeout);
+        self.running.insert(task.uuid(), Running::of(&task));
+        self.update_gauges();
+        self.emit(task.uuid, EventKind::Started, invocation.attempt);
+
+        Some((task, invocation))
+    }
+
+    fn finish_call(
+        &mut self,
+        mut task: Task<T, U, R, E, M>,
+        result: Result<R, E>,
+    ) -> CallOutcome<T, U, R, E, M> {
+        self.running.remove(&task.uuid());
+        self.record_result(task.uuid, &result);
+
+        let mut outcome = CallOutcome {
+            uuid: task.uuid(),
+            result,
+            interrupt: task.token.interrupt(),
+            task: None,
+        };
+
+        task.token.set_timeout(&self.clock, None);
+        self.emit_outcome(&outcome, task.called);
+
+        let now = self.clock.now();
+        let next_run = task.schedule.as_ref().and_then(|e| e.next_after(now));
+        let policy = task.retry_policy.as_ref().or(self.retry_policy.as_ref());
+
+        // a retry too far off for a `SystemTime` would never be due, so the
+        // task is not retried at all
+        let retry_at = match (&outcome.result, policy) {
+            (Err(e), Some(policy)) if policy.should_retry(task.called, e) => {
+                now.checked_add(policy.delay(task.called))
+            }
+            _ => None,
+        };
+
+        match (&outcome.result, policy) {
+            // interrupted tasks are handed back as they are, never retried
+            _ if outcome.interrupt.is_some() => {
+                self.failed.insert(task.uuid);
+                outcome.task = Some(task);
+            }
+            _ if retry_at.is_some() => {
+                task.not_before = retry_at;
+                self.emit(task.uuid, EventKind::Retried, task.called);
+                self.enqueue(task);
+            }
+            // the next run is queued as a new task, and a deadline only ever
+            // applies to the run it was set on
+            _ if next_run.is_some() && !self.draining => {
+                match outcome.result {
+                    Ok(_) => self.remember_key(&task),
+                    Err(_) => {
+                        self.failed.insert(task.uuid);
+                    }
+                }
+
+                task.previous_run = Some(task.uuid);
+                task.uuid = Uuid::new_v4();
+                task.called = 0;
+                task.deadline = None;
+                task.not_before = next_run;
+
+                // the same job, so not turned away by its own key
+                self.emit(task.uuid, EventKind::Queued, task.called);
+                self.enqueue(task);
+            }
+            (Err(_), Some(_)) => {
+                self.emit(task.uuid, EventKind::DeadLettered, task.called);
+                self.failed.insert(task.uuid);
+                self.dead_letters.push(task);
+            }
+            _ => {
+                match outcome.result {
+                    Ok(_) => self.remember_key(&task),
+                    Err(_) => {
+                        self.failed.insert(task.uuid);
+                    }
+                }
+
+                task.not_before = None;
+                outcome.task = Some(task);
+            }
+        }
+
+        self.update_gauges();
+
+        outcome
+    }
+
+    // For a call that panicked: the task goes to the dead letters rather than
+    // being lost, and is reported as failed.
+    fn abandon_call(&mut self, task: Task<T, U, R, E, M>) {
+        self.running.remove(&task.uuid);
+        task.token.set_timeout(&self.clock, None);
+        self.emit(task.uuid, EventKind::Failed, task.called);
+        self.emit(task.uuid, EventKind::DeadLettered, task.called);
+        self.failed.insert(task.uuid);
+        self.dead_letters.push(task);
+        self.update_gauges();
+    }
+
+    pub fn add_dependency(&mut self, uuid: Uuid, depends_on: Uuid) -> Result<(), GraphError> {
+        let index = self.position(uuid).ok_or(GraphError::UnknownTask(uuid))?;
+
+        if !self.contains(depends_on) {
+            return Err(GraphError::UnknownTask(depends_on));
+        }
+
+        if !self.tasks[index].add_dependency(depends_on) {
+            return if uuid == depends_on {
+                Err(GraphError::Cycle(vec![uuid]))
+            } else {
+                Ok(())
+            };
+        }
+
+        if let Err(e) = self.execution_order() {
+            self.tasks[index].remove_dependency(depends_on);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    pub fn execution_order(&self) -> Result<Vec<Uuid>, GraphError> {
+        let nodes = self
+            .tasks
+            .iter()
+            .map(|e| graph::Node {
+                uuid: e.uuid(),
+                dependencies: e.dependencies(),
+                priority: e.priority(),
+            })
+            .collect::<Vec<_>>();
+
+        graph::topological_order(&nodes)
+    }
+
+    pub fn ready(&self) -> Vec<Uuid> {
+        self.tasks
+            .iter()
+            .filter(|e| self.is_ready(e))
+            .map(|e| e.uuid())
+            .collect()
+    }
+
+    fn update_gauges(&self) {
+        #[cfg(feature = "metrics")]
+        for metrics in &self.metrics {
+            self.record_metrics(metrics);
+        }
+    }
+
+    fn emit(&self, uuid: Uuid, kind: EventKind, attempt: u32) {
+        if !self.observers.is_empty() {
+            self.observers.emit(Event {
+                uuid,
+                kind,
+                timestamp: self.clock.now(),
+                attempt,
+            });
+        }
+    }
+
+    fn emit_outcome(&self, outcome: &CallOutcome<T, U, R, E, M>, attempt: u32) {
+        let kind = match outcome.result {
+            Ok(_) => EventKind::Succeeded,
+            Err(_) => EventKind::Failed,
+        };
+
+        self.emit(outcome.uuid, kind, attempt);
+
+        if outcome.interrupt == Some(Interrupt::Cancelled) {
+            self.emit(outcome.uuid, EventKind::Cancelled, attempt);
+        }
+    }
+
+    fn schedule_first(&self, task: &mut Task<T, U, R, E, M>) {
+        if task.not_before.is_none() {
+            task.not_before = task
+                .schedule
+                .as_ref()
+                .and_then(|e| e.first(self.clock.now()));
+        }
+    }
+
+    // When the next task held back by `not_before` or by its quota's rate
+    // becomes due.
+    fn next_wake(&self) -> Option<SystemTime> {
+        let now = self.clock.now();
+
+        self.tasks
+            .iter()
+            .filter(|e| !e.suspended)
+            .filter_map(|e| e.not_before)
+            .chain(self.quota_wake())
+            .filter(|e| *e > now)
+            .min()
+    }
+
+    fn is_ready(&self, task: &Task<T, U, R, E, M>) -> bool {
+        !task.suspended
+            && task.not_before.is_none_or(|e| e <= self.clock.now())
+            && task.prerequisites().all(|e| {
+                // a popped task only holds back the task reading its output,
+                // until the output is recorded
+                !self.contains(*e)
+                    && self.running.get(e).is_none_or(|e| e.popped)
+                    && (task.source != Some(*e) || self.results.contains_key(e))
+                    && !self.failed.contains(e)
+            })
+    }
+
+    // Tasks depending on one that did not succeed go to the dead letters, and
+    // so do the tasks depending on those in turn.
+    fn sweep_dependents(&mut self) {
+        let len = self.dead_letters.len();
+
+        while let Some(i) = self
+            .tasks
+            .iter()
+            .position(|e| e.prerequisites().any(|e| self.failed.contains(e)))
+        {
+            let task = self.tasks.remove(i).unwrap();
+
+            self.emit(task.uuid, EventKind::DeadLettered, task.called);
+            self.failed.insert(task.uuid);
+            self.dead_letters.push(task);
+        }
+
+        // nothing left to hold back for a task no one waits on any more
+        let prerequisites = self
+            .tasks
+            .iter()
+            .flat_map(|e| e.prerequisites())
+            .copied()
+            .chain(self.running.values().filter_map(|e| e.source))
+            .collect::<HashSet<_>>();
+
+        self.failed.retain(|e| prerequisites.contains(e));
+
+        if self.dead_letters.len() != len {
+            self.relink();
+            self.update_gauges();
+        }
+    }
+
+    // Tasks whose token was cancelled from elsewhere leave the queue as well.
+    fn sweep_cancelled(&mut self) {
+        let len = self.cancelled.len();
+
+        let mut i = 0;
+        while i < self.tasks.len() {
+            if self.tasks[i].is_cancelled() {
+                let task = self.tasks.remove(i).unwrap();
+
+                self.emit(task.uuid, EventKind::Cancelled, task.called);
+                self.failed.insert(task.uuid);
+                self.cancelled.push(task);
+            } else {
+                i += 1;
+            }
+        }
+
+        if self.cancelled.len() != len {
+            self.relink();
+            self.update_gauges();
+        }
+    }
+
+    fn insert(&mut self, index: usize, mut task: Task<T, U, R, E, M>) {
+        self.schedule_first(&mut task);
+        self.tasks.insert(index, task);
+        self.relink();
+        self.update_gauges();
+    }
+
+    fn position(&self, uuid: Uuid) -> Option<usize> {
+        self.tasks.iter().position(|e| e.uuid() == uuid)
+    }
+
+    fn relink(&mut self) {
+        let uuids = self.tasks.iter().map(|e| e.uuid()).collect::<Vec<_>>();
+
+        for (i, task) in self.tasks.iter_mut().enumerate() {
+            task.update_before(if i > 0 { Some(uuids[i - 1]) } else { None });
+            task.update_after(uuids.get(i + 1).copied());
+        }
+    }
+}
+
+impl<T, U, R, E, M: Threading> Default for TaskManager<T, U, R, E, M> {
+    fn default() -> Self {
+        Self::new_in(M::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_uuid() {
+        let task1 = Task::<_, i32>::new(1);
+        let task2 = Task::<_, i32>::new(1);
+
+        assert_ne!(task1.uuid, task2.uuid);
+    }
+
+    #[test]
+    fn test_len() {
+        let mut manager = TaskManager::<_, _>::new();
+
+        assert_eq!(0, manager.len());
+
+        manager.push(Task::new(1));
+        manager.push(Task::new(2));
+        manager.push(Task::new(3));
+        manager.push(Task::new(4));
+
+        assert_eq!(4, manager.len());
+
+        manager.pop();
+
+        assert_eq!(3, manager.len());
+
+        manager.clear();
+
+        assert_eq!(0, manager.len());
+
+        manager.pop_and_call(1);
+        assert_eq!(0, manager.len());
+    }
+
+    #[test]
+    fn test_call() {
+        let mut task = Task::<_, _, i32, String>::new(1);
+
+        assert_eq!(0, task.called());
+        assert_eq!(&1, task.data());
+
+        assert_eq!(Ok(0), task.call(1));
+
+        assert_eq!(1, task.called());
+
+        task.set_callback(|this, arg| {
+            assert_eq!(2, *arg);
+
+            this.data = 3;
+
+            Err("failed".to_string())
+        });
+
+        assert_eq!(Err("failed".to_string()), task.call(2));
+        assert_eq!(&3, task.data());
+        assert_eq!(1, Arc::strong_count(&task.do_callback));
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison, unused_variables)]
+    fn test_dry_run() {
+        let mut task = Task::<_, _>::new(1);
+
+        assert_eq!(0, task.called());
+
+        assert_eq!(true, task.dry_run(1));
+
+        assert_eq!(0, task.called());
+
+        task.set_dry_run_callback(|this, arg| {
+            assert_eq!(2, *arg);
+
+            false
+        });
+
+        assert_eq!(false, task.dry_run(2));
+        assert_eq!(1, Arc::strong_count(&task.do_callback));
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let mut manager = TaskManager::<_, i32>::new();
+
+        let task1 = Task::new(1);
+        let task2 = Task::new(2);
+        let task3 = Task::new(3);
+
+        let (uuid1, uuid2, uuid3) = (task1.uuid(), task2.uuid(), task3.uuid());
+
+        manager.push(task1);
+        manager.push(task2);
+        manager.push(task3);
+
+        assert!(manager.add_dependency(uuid1, uuid3).is_ok());
+        assert!(manager.add_dependency(uuid2, uuid1).is_ok());
+
+        assert_eq!(vec![uuid3], manager.ready());
+        assert_eq!(
+            vec![uuid3, uuid1, uuid2],
+            manager.execution_order().unwrap()
+        );
+
+        let task = manager.pop().unwrap();
+        assert_eq!(uuid3, task.uuid());
+        assert_eq!(vec![uuid1], manager.ready());
+
+        assert_eq!(&1, manager.pop().unwrap().data());
+        assert_eq!(&2, manager.pop().unwrap().data());
+        assert!(manager.pop().is_none());
+    }
+
+    #[test]
+    fn test_failed_dependency() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let clock = Arc::new(clock::ManualClock::default());
+        let mut manager = TaskManager::<_, (), (), ()>::with_clock(clock.clone());
+
+        manager.subscribe(sender);
+
+        let mut failing = Task::new(0);
+        failing.set_callback(|_, _| Err(()));
+
+        let mut late = Task::new(1);
+        late.set_deadline(Some(clock.now()));
+
+        let cancelled = Task::new(2);
+        let tasks = (3..7).map(Task::new).collect::<Vec<_>>();
+        let uuids = [failing.uuid(), late.uuid(), cancelled.uuid()]
+            .into_iter()
+            .chain(tasks.iter().map(|e| e.uuid()))
+            .collect::<Vec<_>>();
+
+        manager.push(failing);
+        manager.push(late);
+        manager.push(cancelled);
+
+        for task in tasks {
+            manager.push(task);
+        }
+
+        // 3, 4 and 5 each depend on one of the first three, 6 on 3
+        for i in 0..3 {
+            manager.add_dependency(uuids[i + 3], uuids[i]).unwrap();
+        }
+
+        manager.add_dependency(uuids[6], uuids[3]).unwrap();
+        manager.cancel(uuids[2]);
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(1, manager.call_all(&()).len());
+        assert!(manager.is_empty());
+        assert_eq!(
+            vec![4, 5, 3, 6],
+            manager
+                .dead_letters()
+                .iter()
+                .map(|e| *e.data())
+                .collect::<Vec<_>>()
+        );
+        // once every dependent is swept, the failures are not kept around
+        assert!(manager.failed.is_empty());
+
+        drop(manager);
+
+        let events = receiver
+            .iter()
+            .filter(|e| !matches!(e.kind, EventKind::Queued))
+            .map(|e| (e.uuid, e.kind))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (uuids[2], EventKind::Cancelled),
+                (uuids[1], EventKind::Expired),
+                (uuids[4], EventKind::DeadLettered),
+                (uuids[5], EventKind::DeadLettered),
+                (uuids[0], EventKind::Started),
+                (uuids[0], EventKind::Failed),
+                (uuids[3], EventKind::DeadLettered),
+                (uuids[6], EventKind::DeadLettered),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let mut manager = TaskManager::<_, i32>::new();
+
+        let task1 = Task::new(1);
+        let task2 = Task::new(2);
+        let task3 = Task::new(3);
+
+        let (uuid1, uuid2, uuid3) = (task1.uuid(), task2.uuid(), task3.uuid());
+
+        manager.push(task1);
+        manager.push(task2);
+        manager.push(task3);
+
+        assert!(manager.add_dependency(uuid1, uuid2).is_ok());
+        assert!(manager.add_dependency(uuid2, uuid3).is_ok());
+
+        match manager.add_dependency(uuid3, uuid1) {
+            Err(GraphError::Cycle(cycle)) => {
+                assert_eq!(3, cycle.len());
+                assert!(cycle.contains(&uuid1));
+                assert!(cycle.contains(&uuid2));
+                assert!(cycle.contains(&uuid3));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        assert_eq!(
+            Err(GraphError::Cycle(vec![uuid1])),
+            manager.add_dependency(uuid1, uuid1)
+        );
+
+        let unknown = Uuid::new_v4();
+        assert_eq!(
+            Err(GraphError::UnknownTask(unknown)),
+            manager.add_dependency(uuid1, unknown)
+        );
+
+        assert_eq!(
+            vec![uuid3, uuid2, uuid1],
+            manager.execution_order().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        let mut manager = TaskManager::<_, i32>::new();
+
+        let task1 = Task::new(1);
+        let task2 = Task::new(2);
+        let task3 = Task::new(3);
+
+        let (uuid1, uuid2, uuid3) = (task1.uuid(), task2.uuid(), task3.uuid());
+
+        manager.push(task1);
+        manager.push(task2);
+        manager.push(task3);
+
+        manager.add_dependency(uuid1, uuid2).unwrap();
+
+        assert_eq!(uuid2, manager.pop().unwrap().uuid());
+
+        let first = manager.tasks.front().unwrap();
+        let last = manager.tasks.back().unwrap();
+
+        assert_eq!((None, Some(uuid3)), (first.before, first.after));
+        assert_eq!((Some(uuid1), None), (last.before, last.after));
+    }
+
+    #[test]
+    fn test_retry() {
+        let clock = Arc::new(clock::ManualClock::default());
+        let mut manager = TaskManager::with_clock(clock.clone());
+
+        manager.set_retry_policy(Some(
+            RetryPolicy::new(3)
+                .with_backoff(Backoff::Fixed(Duration::from_secs(10)))
+                .retry_if(|e: &String| e != "fatal"),
+        ));
+
+        let mut flaky = Task::new(1);
+        flaky.set_callback(|this, _| match this.called() {
+            1 => Err("busy".to_string()),
+            e => Ok(e),
+        });
+
+        let mut broken = Task::new(2);
+        broken.set_callback(|_, _| Err("broken".to_string()));
+        broken.set_retry_policy(Some(RetryPolicy::new(2)));
+
+        let mut fatal = Task::new(3);
+        fatal.set_callback(|_, _| Err("fatal".to_string()));
+
+        let (flaky_uuid, broken_uuid) = (flaky.uuid(), broken.uuid());
+
+        manager.push(flaky);
+        manager.push(broken);
+
+        assert_eq!(
+            Some((flaky_uuid, Err("busy".to_string()), None)),
+            take(manager.pop_and_call(1))
+        );
+        assert_eq!(2, manager.len());
+        assert_eq!(
+            Some(clock.now() + Duration::from_secs(10)),
+            manager.next_due()
+        );
+
+        // the flaky task waits for its backoff, the broken one retries at once
+        assert_eq!(
+            Some((broken_uuid, Err("broken".to_string()), None)),
+            take(manager.pop_and_call(1))
+        );
+        assert_eq!(2, manager.len());
+        assert!(manager.dead_letters().is_empty());
+
+        assert_eq!(
+            Some((broken_uuid, Err("broken".to_string()), None)),
+            take(manager.pop_and_call(1))
+        );
+        assert_eq!(1, manager.len());
+        assert_eq!(broken_uuid, manager.dead_letters()[0].uuid());
+        assert_eq!(2, manager.dead_letters()[0].called());
+
+        assert!(manager.pop_and_call(1).is_none());
+        assert_eq!(1, manager.len());
+
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(
+            Some((flaky_uuid, Ok(2), Some(2))),
+            take(manager.pop_and_call(1))
+        );
+        assert!(manager.is_empty());
+
+        // errors rejected by the predicate go straight to the dead letters
+        let fatal_uuid = fatal.uuid();
+        manager.push(fatal);
+
+        assert_eq!(
+            Some((fatal_uuid, Err("fatal".to_string()), None)),
+            take(manager.pop_and_call(1))
+        );
+        assert_eq!(2, manager.take_dead_letters().len());
+        assert!(manager.dead_letters().is_empty());
+
+        // a backoff past the end of time is never waited out
+        let mut broken = Task::new(4);
+        broken.set_callback(|_, _| Err("broken".to_string()));
+        broken.set_retry_policy(Some(
+            RetryPolicy::new(2).with_backoff(Backoff::Fixed(Duration::MAX)),
+        ));
+        manager.push(broken);
+
+        assert!(manager.pop_and_call(1).unwrap().task.is_none());
+        assert_eq!(1, manager.dead_letters().len());
+    }
+
+    fn take<T, U, R, E>(
+        outcome: Option<CallOutcome<T, U, R, E>>,
+    ) -> Option<(Uuid, Result<R, E>, Option<u32>)> {
+        outcome.map(|e| (e.uuid, e.result, e.task.map(|e| e.called())))
+    }
+
+    #[test]
+    fn test_call_all() {
+        let mut manager = TaskManager::new();
+
+        for i in 0..4 {
+            let mut task = Task::new(i);
+
+            task.set_callback(|this, arg: &i32| {
+                if this.data() % 2 == 0 {
+                    Ok(this.data() * arg)
+                } else {
+                    Err(format!("odd {}", this.data()))
+                }
+            });
+
+            manager.push(task);
+        }
+
+        let summary = manager.call_all(&10);
+
+        assert_eq!(4, summary.len());
+        assert!(!summary.is_success());
+        assert_eq!(
+            vec![0, 20],
+            summary.succeeded().map(|(_, e)| *e).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["odd 1", "odd 3"],
+            summary
+                .failed()
+                .map(|(_, e)| e.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_middleware() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut manager = TaskManager::new();
+
+        let hook_log = log.clone();
+        manager.add_middleware(Hooks::new().after(move |e, result: &Result<(), ()>, _| {
+            hook_log
+                .lock()
+                .unwrap()
+                .push((e.uuid, e.operation, e.attempt, result.is_ok()));
+        }));
+
+        let mut vetoed = Task::new(1);
+        vetoed.set_kind("vetoed");
+        let vetoed_uuid = vetoed.uuid();
+
+        manager.add_middleware(
+            move |e: &Invocation, next: &mut dyn FnMut() -> Result<(), ()>| {
+                if e.uuid == vetoed_uuid {
+                    return Err(());
+                }
+
+                next()
+            },
+        );
+
+        let task = Task::new(2);
+        let uuid = task.uuid();
+
+        manager.push(vetoed);
+        manager.push(task);
+
+        assert_eq!(
+            Some((vetoed_uuid, Err(()), Some(0))),
+            take(manager.pop_and_call(1))
+        );
+        assert_eq!(Some((uuid, Ok(()), Some(1))), take(manager.pop_and_call(1)));
+        assert_eq!(2, manager.middleware().len());
+        assert_eq!(
+            vec![
+                (vetoed_uuid, Operation::Call, 1, false),
+                (uuid, Operation::Call, 1, true)
+            ],
+            *log.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut manager = TaskManager::<_, ()>::new();
+
+        let bulk1 = Task::new("bulk1");
+        let bulk2 = Task::new("bulk2");
+
+        let mut urgent1 = Task::new("urgent1");
+        urgent1.set_priority(10);
+
+        let mut urgent2 = Task::new("urgent2");
+        urgent2.set_priority(10);
+
+        let bulk2_uuid = bulk2.uuid();
+
+        manager.push(bulk1);
+        manager.push(bulk2);
+        manager.push(urgent1);
+        manager.push(urgent2);
+
+        assert!(manager.set_priority(bulk2_uuid, 5));
+        assert!(!manager.set_priority(Uuid::new_v4(), 5));
+
+        let order = manager
+            .execution_order()
+            .unwrap()
+            .into_iter()
+            .map(|e| *manager.tasks[manager.position(e).unwrap()].data())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["urgent1", "urgent2", "bulk2", "bulk1"], order);
+
+        let mut popped = Vec::new();
+        while let Some(task) = manager.pop() {
+            popped.push(*task.data());
+        }
+
+        assert_eq!(order, popped);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let clock = Arc::new(clock::ManualClock::default());
+        let mut manager = TaskManager::<_, ()>::with_clock(clock.clone());
+
+        let mut late = Task::new(1);
+        late.set_deadline(Some(clock.now() + Duration::from_secs(5)));
+
+        let mut on_time = Task::new(2);
+        on_time.set_deadline(Some(clock.now() + Duration::from_secs(20)));
+
+        let late_uuid = late.uuid();
+
+        manager.push(Task::new(0));
+        manager.push(late);
+        manager.push(on_time);
+
+        assert_eq!(&0, manager.pop().unwrap().data());
+
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(&2, manager.pop().unwrap().data());
+        assert!(manager.pop().is_none());
+        assert_eq!(late_uuid, manager.expired()[0].uuid());
+        assert_eq!(0, manager.expired()[0].called());
+        assert_eq!(1, manager.take_expired().len());
+        assert!(manager.expired().is_empty());
+    }
+
+    #[test]
+    fn test_random_access() {
+        let mut manager = TaskManager::<_, ()>::new();
+
+        let tasks = (0..4).map(Task::new).collect::<Vec<_>>();
+        let uuids = tasks.iter().map(|e| e.uuid()).collect::<Vec<_>>();
+
+        for task in tasks {
+            manager.push(task);
+        }
+
+        let data =
+            |manager: &TaskManager<i32, ()>| manager.iter().map(|e| *e.data()).collect::<Vec<_>>();
+        let links = |manager: &TaskManager<i32, ()>| {
+            let tasks = manager.iter().collect::<Vec<_>>();
+
+            tasks.iter().enumerate().all(|(i, e)| {
+                e.before == i.checked_sub(1).map(|i| tasks[i].uuid())
+                    && e.after == tasks.get(i + 1).map(|e| e.uuid())
+            })
+        };
+
+        assert_eq!(Some(&2), manager.get(uuids[2]).map(|e| e.data()));
+        assert!(manager.get(Uuid::new_v4()).is_none());
+
+        manager.get_mut(uuids[2]).unwrap().set_kind("edited");
+        assert_eq!(Some("edited"), manager.get(uuids[2]).unwrap().kind());
+
+        assert_eq!(&1, manager.remove(uuids[1]).unwrap().data());
+        assert!(manager.remove(uuids[1]).is_none());
+        assert_eq!(vec![0, 2, 3], data(&manager));
+        assert!(links(&manager));
+
+        manager.insert_before(uuids[0], Task::new(4)).unwrap();
+        manager.insert_after(uuids[3], Task::new(5)).unwrap();
+        manager.insert_after(uuids[0], Task::new(6)).unwrap();
+        assert_eq!(vec![4, 0, 6, 2, 3, 5], data(&manager));
+        assert!(links(&manager));
+
+        let task = Task::new(7);
+        let uuid = task.uuid();
+        let error = manager.insert_before(uuids[1], task).unwrap_err();
+
+        // the task is handed back rather than dropped
+        assert_eq!(uuids[1], error.anchor);
+        assert_eq!(uuid, error.task.uuid());
+        assert!(manager.insert_after(uuids[1], *error.task).is_err());
+
+        assert!(manager.move_to(uuids[3], 0));
+        assert!(manager.move_to(uuids[0], 100));
+        assert!(!manager.move_to(uuids[1], 0));
+        assert_eq!(vec![3, 4, 6, 2, 5, 0], data(&manager));
+        assert!(links(&manager));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let clock = Arc::new(clock::ManualClock::default());
+        let mut manager = TaskManager::<i32, (), (), String>::with_clock(clock.clone());
+
+        manager.set_retry_policy(Some(RetryPolicy::new(3)));
+
+        let mut slow = Task::new(0);
+        let slow_clock = clock.clone();
+
+        slow.set_timeout(Some(Duration::from_secs(5)));
+        slow.set_callback(move |this, _| {
+            slow_clock.advance(Duration::from_secs(10));
+
+            if this.is_cancelled() {
+                return Err("stopped".to_string());
+            }
+
+            Ok(())
+        });
+
+        let tasks = (1..4).map(Task::new).collect::<Vec<_>>();
+        let uuids = tasks.iter().map(|e| e.uuid()).collect::<Vec<_>>();
+        let external = tasks[1].token().clone();
+        let slow_uuid = slow.uuid();
+
+        manager.push(slow);
+
+        for task in tasks {
+            manager.push(task);
+        }
+
+        assert!(manager.cancel(uuids[0]));
+        assert!(!manager.cancel(uuids[0]));
+
+        external.cancel();
+
+        let outcome = manager.pop_and_call(()).unwrap();
+
+        assert_eq!(slow_uuid, outcome.uuid);
+        assert_eq!(Some(Interrupt::TimedOut), outcome.interrupt);
+        assert_eq!(Err("stopped".to_string()), outcome.result);
+        assert!(!outcome.task.unwrap().is_cancelled());
+        assert!(manager.dead_letters().is_empty());
+
+        assert_eq!(
+            vec![1, 2],
+            manager
+                .cancelled()
+                .iter()
+                .map(|e| *e.data())
+                .collect::<Vec<_>>()
+        );
+
+        // a popped task is handed over for good, so is not counted
+        manager.push(Task::new(4));
+        assert!(manager.pop().is_some());
+
+        assert_eq!(1, manager.cancel_all());
+        assert!(manager.is_empty());
+        assert!(manager.take_cancelled().iter().all(|e| e.is_cancelled()));
+    }
+
+    #[test]
+    fn test_schedule() {
+        // Friday 2024-03-01 10:07:00 UTC
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_287_620);
+        let clock = Arc::new(clock::ManualClock::new(start));
+        let mut manager = TaskManager::<_, ()>::with_clock(clock.clone());
+
+        let mut report = Task::new("report");
+        report
+            .set_schedule(Some(Schedule::Cron(Cron::parse("*/15 * * * *").unwrap())))
+            .unwrap();
+
+        let mut once = Task::new("once");
+        once.set_schedule(Some(Schedule::At(start + Duration::from_secs(20 * 60))))
+            .unwrap();
+
+        let first = report.uuid();
+
+        assert_eq!(
+            Err(ScheduleError::ZeroInterval),
+            once.set_schedule(Some(Schedule::Every(Duration::ZERO)))
+        );
+
+        manager.push(report);
+        manager.push(once);
+
+        assert!(manager.pop_and_call(()).is_none());
+
+        let summary = manager.run_until(&(), start + Duration::from_secs(60 * 60));
+
+        // 10:15, 10:27 once, 10:30, 10:45 and 11:00
+        assert_eq!(5, summary.len());
+        assert_eq!(first, summary.outcomes[0].0);
+        assert_eq!(start + Duration::from_secs(53 * 60), clock.now());
+
+        let reports = summary
+            .outcomes
+            .iter()
+            .map(|e| e.0)
+            .filter(|e| *e != summary.outcomes[1].0)
+            .collect::<Vec<_>>();
+
+        assert_eq!(4, reports.len());
+
+        let next = manager.iter().next().unwrap();
+
+        assert_eq!(1, manager.len());
+        assert_eq!(Some(reports[3]), next.previous_run());
+        assert_eq!(0, next.called());
+        assert_eq!(
+            Some(start + Duration::from_secs(68 * 60)),
+            next.not_before()
+        );
+    }
+
+    #[test]
+    fn test_suspend() {
+        let mut manager = TaskManager::<_, ()>::new();
+        let (a, b, c) = (Task::new(1), Task::new(2), Task::new(3));
+        let (a_uuid, c_uuid) = (a.uuid(), c.uuid());
+
+        manager.push(a);
+        manager.push(b);
+        manager.push(c);
+        manager.add_dependency(c_uuid, a_uuid).unwrap();
+
+        assert!(manager.suspend(a_uuid));
+
+        // the dependent of a suspended task is held back too
+        assert_eq!(&2, manager.pop().unwrap().data());
+        assert!(manager.pop().is_none());
+        assert_eq!(2, manager.len());
+
+        assert!(manager.resume_task(a_uuid));
+        assert!(!manager.resume_task(Uuid::new_v4()));
+
+        assert_eq!(&1, manager.pop().unwrap().data());
+        assert_eq!(&3, manager.pop().unwrap().data());
+    }
+
+    #[test]
+    fn test_pause_and_drain() {
+        let clock = Arc::new(clock::ManualClock::new(SystemTime::UNIX_EPOCH));
+        let mut manager = TaskManager::<_, ()>::with_clock(clock.clone());
+
+        let mut every = Task::new("every");
+        every
+            .set_schedule(Some(Schedule::Every(Duration::from_secs(60))))
+            .unwrap();
+
+        let mut suspended = Task::new("suspended");
+        suspended.suspend();
+
+        manager.push(Task::new("now"));
+        manager.push(every);
+        manager.push(suspended);
+        manager.pause();
+
+        assert!(manager.is_paused());
+        assert!(manager.pop_and_call(()).is_none());
+        assert!(manager.call_all(&()).is_empty());
+
+        let summary = manager.drain(&());
+
+        // the recurring task ran once, after waiting for it to be due
+        assert_eq!(2, summary.len());
+        assert!(!manager.is_paused());
+        assert!(manager.is_draining());
+        assert_eq!(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+            clock.now()
+        );
+        assert_eq!(
+            vec![&"suspended"],
+            manager.iter().map(|e| e.data()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Err(PushError::Draining),
+            manager.try_push(Task::new("late"))
+        );
+
+        // resuming is not enough to end the drain
+        manager.pause();
+        manager.resume();
+
+        assert!(manager.is_draining());
+
+        manager.end_drain();
+
+        assert!(manager.try_push(Task::new("late")).is_ok());
+    }
+
+    #[test]
+    fn test_events() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut manager = TaskManager::<_, ()>::new();
+
+        manager.subscribe(sender);
+        manager.set_retry_policy(Some(RetryPolicy::new(2)));
+
+        let mut flaky = Task::new(0);
+        flaky.set_callback(|this, _| if this.called() == 1 { Err(()) } else { Ok(()) });
+
+        let other = Task::new(1);
+        let cancelled = Task::new(2);
+        let (a, b, c) = (flaky.uuid(), other.uuid(), cancelled.uuid());
+
+        manager.push(flaky);
+        manager.push(other);
+
+        assert_eq!(3, manager.call_all(&()).len());
+
+        manager.push(cancelled);
+        manager.cancel(c);
+
+        drop(manager);
+
+        assert_eq!(
+            vec![
+                (a, EventKind::Queued, 0),
+                (b, EventKind::Queued, 0),
+                (a, EventKind::Started, 1),
+                (a, EventKind::Failed, 1),
+                (a, EventKind::Retried, 1),
+                (b, EventKind::Started, 1),
+                (b, EventKind::Succeeded, 1),
+                (a, EventKind::Started, 2),
+                (a, EventKind::Succeeded, 2),
+                (c, EventKind::Queued, 0),
+                (c, EventKind::Cancelled, 0),
+            ],
+            receiver
+                .iter()
+                .map(|e| (e.uuid, e.kind, e.attempt))
+                .collect::<Vec<_>>()
+        );
     }
 }
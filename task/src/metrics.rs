@@ -0,0 +1,432 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+use crate::executor::BoxFuture;
+use crate::{Holds, Invocation, Middleware, Operation, TaskManager, Threading};
+
+// upper bounds of the duration histogram buckets, in seconds
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 10.0];
+const OPERATIONS: [Operation; 3] = [Operation::Call, Operation::Execute, Operation::Rollback];
+const TIMING_CAPACITY: usize = 1024;
+
+// One timed call, execute or rollback of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub uuid: Uuid,
+    pub operation: Operation,
+    pub attempt: u32,
+    pub start: SystemTime,
+    pub duration: Duration,
+    pub succeeded: bool,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    succeeded: u64,
+    failed: u64,
+    retries: u64,
+}
+
+#[derive(Debug)]
+struct Registry {
+    stats: [Stats; OPERATIONS.len()],
+    gauges: Vec<(&'static str, &'static str, u64)>,
+    timings: VecDeque<Timing>,
+}
+
+// An in-process registry, filled in as middleware on a `TaskManager` or an
+// `Invoker`. Clones share the same registry.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            registry: Arc::new(Mutex::new(Registry {
+                stats: Default::default(),
+                gauges: Vec::new(),
+                timings: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn succeeded(&self, operation: Operation) -> u64 {
+        self.lock().stats[index(operation)].succeeded
+    }
+
+    pub fn failed(&self, operation: Operation) -> u64 {
+        self.lock().stats[index(operation)].failed
+    }
+
+    // Attempts after the first one.
+    pub fn retries(&self, operation: Operation) -> u64 {
+        self.lock().stats[index(operation)].retries
+    }
+
+    pub fn gauge(&self, name: &str) -> Option<u64> {
+        self.lock().gauges.iter().find(|e| e.0 == name).map(|e| e.2)
+    }
+
+    // The most recent timings, oldest first; older ones are dropped past
+    // 1024.
+    pub fn timings(&self) -> Vec<Timing> {
+        self.lock().timings.iter().copied().collect()
+    }
+
+    // Everything recorded so far, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP task_duration_seconds Time spent calling, executing and rolling back tasks."
+        );
+        let _ = writeln!(out, "# TYPE task_duration_seconds histogram");
+
+        for (operation, stats) in OPERATIONS.iter().zip(&registry.stats) {
+            let operation = name(*operation);
+            let mut count = 0;
+
+            for (le, bucket) in BUCKETS.iter().zip(stats.buckets) {
+                count += bucket;
+                let _ = writeln!(
+                    out,
+                    "task_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}",
+                    operation, le, count
+                );
+            }
+
+            let total = stats.succeeded + stats.failed;
+
+            let _ = writeln!(
+                out,
+                "task_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}",
+                operation, total
+            );
+            let _ = writeln!(
+                out,
+                "task_duration_seconds_sum{{operation=\"{}\"}} {}",
+                operation, stats.sum
+            );
+            let _ = writeln!(
+                out,
+                "task_duration_seconds_count{{operation=\"{}\"}} {}",
+                operation, total
+            );
+        }
+
+        let _ = writeln!(out, "# HELP task_outcomes_total Finished task operations.");
+        let _ = writeln!(out, "# TYPE task_outcomes_total counter");
+
+        for (operation, stats) in OPERATIONS.iter().zip(&registry.stats) {
+            for (outcome, count) in [("success", stats.succeeded), ("failure", stats.failed)] {
+                let _ = writeln!(
+                    out,
+                    "task_outcomes_total{{operation=\"{}\",outcome=\"{}\"}} {}",
+                    name(*operation),
+                    outcome,
+                    count
+                );
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP task_retries_total Attempts after the first one."
+        );
+        let _ = writeln!(out, "# TYPE task_retries_total counter");
+
+        for (operation, stats) in OPERATIONS.iter().zip(&registry.stats) {
+            let _ = writeln!(
+                out,
+                "task_retries_total{{operation=\"{}\"}} {}",
+                name(*operation),
+                stats.retries
+            );
+        }
+
+        for (gauge, help, value) in &registry.gauges {
+            let _ = writeln!(out, "# HELP {} {}", gauge, help);
+            let _ = writeln!(out, "# TYPE {} gauge", gauge);
+            let _ = writeln!(out, "{} {}", gauge, value);
+        }
+
+        out
+    }
+
+    fn time(&self, invocation: &Invocation, start: SystemTime, timer: Instant, succeeded: bool) {
+        self.record(Timing {
+            uuid: invocation.uuid,
+            operation: invocation.operation,
+            attempt: invocation.attempt,
+            start,
+            duration: timer.elapsed(),
+            succeeded,
+        });
+    }
+
+    fn record(&self, timing: Timing) {
+        let mut registry = self.lock();
+        let stats = &mut registry.stats[index(timing.operation)];
+        let seconds = timing.duration.as_secs_f64();
+
+        if let Some(i) = BUCKETS.iter().position(|e| seconds <= *e) {
+            stats.buckets[i] += 1;
+        }
+
+        stats.sum += seconds;
+
+        if timing.succeeded {
+            stats.succeeded += 1;
+        } else {
+            stats.failed += 1;
+        }
+
+        if timing.attempt > 1 {
+            stats.retries += 1;
+        }
+
+        if registry.timings.len() == TIMING_CAPACITY {
+            registry.timings.pop_front();
+        }
+
+        registry.timings.push_back(timing);
+    }
+
+    fn set_gauge(&self, gauge: &'static str, help: &'static str, value: usize) {
+        let mut registry = self.lock();
+
+        match registry.gauges.iter_mut().find(|e| e.0 == gauge) {
+            Some(e) => e.2 = value as u64,
+            None => registry.gauges.push((gauge, help, value as u64)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R, E> Middleware<Result<R, E>> for Metrics {
+    fn handle(
+        &self,
+        invocation: &Invocation,
+        next: &mut dyn FnMut() -> Result<R, E>,
+    ) -> Result<R, E> {
+        let start = SystemTime::now();
+        let timer = Instant::now();
+        let result = next();
+
+        self.time(invocation, start, timer, result.is_ok());
+
+        result
+    }
+
+    // Timed around the awaited call, so concurrent calls overlap rather than
+    // being run one at a time.
+    fn handle_async<'f>(
+        &'f self,
+        invocation: &'f Invocation,
+        next: BoxFuture<'f, Result<R, E>>,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        Result<R, E>: 'f,
+    {
+        Box::pin(async move {
+            let start = SystemTime::now();
+            let timer = Instant::now();
+            let result = next.await;
+
+            self.time(invocation, start, timer, result.is_ok());
+
+            result
+        })
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    // Adds `metrics` as middleware and keeps its gauges current as tasks are
+    // pushed, popped, called and moved to the other lists.
+    pub fn add_metrics(&mut self, metrics: Metrics)
+    where
+        M: Holds<Metrics>,
+    {
+        self.record_metrics(&metrics);
+        self.add_middleware(metrics.clone());
+        self.metrics.push(metrics);
+    }
+
+    // Sets the gauges once, for metrics not added with `add_metrics`.
+    pub fn record_metrics(&self, metrics: &Metrics) {
+        metrics.set_gauge(
+            "task_queue_depth",
+            "Tasks waiting in the queue.",
+            self.len(),
+        );
+        metrics.set_gauge("task_running", "Tasks being called.", self.running.len());
+        metrics.set_gauge(
+            "task_dead_letters",
+            "Tasks that ran out of attempts or whose dependencies failed.",
+            self.dead_letters.len(),
+        );
+        metrics.set_gauge(
+            "task_cancelled",
+            "Tasks cancelled before they ran.",
+            self.cancelled.len(),
+        );
+        metrics.set_gauge(
+            "task_expired",
+            "Tasks whose deadline passed before they ran.",
+            self.expired.len(),
+        );
+    }
+}
+
+fn index(operation: Operation) -> usize {
+    match operation {
+        Operation::Call => 0,
+        Operation::Execute => 1,
+        Operation::Rollback => 2,
+    }
+}
+
+fn name(operation: Operation) -> &'static str {
+    match operation {
+        Operation::Call => "call",
+        Operation::Execute => "execute",
+        Operation::Rollback => "rollback",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{yield_now, BlockingExecutor};
+    use crate::{Invoker, RetryPolicy, Task};
+
+    #[test]
+    fn test_task_manager() {
+        let metrics = Metrics::new();
+        let mut manager = TaskManager::<i32, (), (), ()>::new();
+
+        manager.add_metrics(metrics.clone());
+        manager.set_retry_policy(Some(RetryPolicy::new(2)));
+
+        for i in 0..3 {
+            let mut task = Task::new(i);
+
+            // the second task fails on its first attempt
+            task.set_callback(|this, _| match this.data() == &1 && this.called() == 1 {
+                true => Err(()),
+                false => Ok(()),
+            });
+
+            manager.push(task);
+        }
+
+        manager.push(Task::new(3));
+
+        assert_eq!(Some(4), metrics.gauge("task_queue_depth"));
+
+        let popped = manager.acquire().unwrap();
+
+        assert_eq!(Some(3), metrics.gauge("task_queue_depth"));
+        assert_eq!(Some(1), metrics.gauge("task_running"));
+
+        manager.release(popped.uuid());
+
+        assert_eq!(Some(0), metrics.gauge("task_running"));
+
+        manager.push(popped);
+        manager.call_all(&());
+
+        assert_eq!(4, metrics.succeeded(Operation::Call));
+        assert_eq!(1, metrics.failed(Operation::Call));
+        assert_eq!(1, metrics.retries(Operation::Call));
+        assert_eq!(Some(0), metrics.gauge("task_queue_depth"));
+        assert_eq!(5, metrics.timings().len());
+
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE task_duration_seconds histogram\n"));
+        assert!(text.contains("task_duration_seconds_bucket{operation=\"call\",le=\"+Inf\"} 5\n"));
+        assert!(text.contains("task_duration_seconds_count{operation=\"call\"} 5\n"));
+        assert!(text.contains("task_outcomes_total{operation=\"call\",outcome=\"failure\"} 1\n"));
+        assert!(text.contains("task_retries_total{operation=\"call\"} 1\n"));
+        assert!(text.contains("# TYPE task_queue_depth gauge\ntask_queue_depth 0\n"));
+    }
+
+    #[test]
+    fn test_invoker() {
+        let metrics = Metrics::new();
+        let mut data = 0;
+        let mut invoker = Invoker::new(&mut data);
+
+        invoker.add_middleware(metrics.clone());
+        invoker.push(crate::FnCommand::new(|data: &mut i32, _: &mut ()| {
+            *data += 1;
+            Ok::<_, ()>(*data)
+        }));
+
+        invoker.execute(&mut ()).unwrap();
+        invoker.undo(&mut ()).unwrap();
+
+        let timings = metrics.timings();
+
+        assert_eq!(1, metrics.succeeded(Operation::Execute));
+        assert_eq!(1, metrics.succeeded(Operation::Rollback));
+        assert_eq!(
+            vec![Operation::Execute, Operation::Rollback],
+            timings.iter().map(|e| e.operation).collect::<Vec<_>>()
+        );
+        assert!(timings.iter().all(|e| e.uuid == timings[0].uuid));
+    }
+
+    #[test]
+    fn test_call_concurrent() {
+        let metrics = Metrics::new();
+        let mut manager = TaskManager::<i32, (), (), ()>::new();
+
+        manager.add_metrics(metrics.clone());
+
+        for i in 0..2 {
+            let mut task = Task::new(i);
+
+            task.set_async_callback(|_, _| {
+                Box::pin(async {
+                    yield_now().await;
+                    std::thread::sleep(Duration::from_millis(20));
+
+                    Ok(())
+                })
+            });
+            manager.push(task);
+        }
+
+        manager.call_concurrent_blocking(&BlockingExecutor, &(), 2);
+
+        let timings = metrics.timings();
+
+        // both calls were in flight at once, and each is timed until it ended
+        assert_eq!(2, timings.len());
+        assert!(timings[1].start < timings[0].start + timings[0].duration);
+        assert!(timings
+            .iter()
+            .all(|e| e.duration >= Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::executor::{BlockingExecutor, BoxFuture, Executor};
+use crate::{Holds, Local, Threading};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Call,
+    Execute,
+    Rollback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Invocation {
+    pub uuid: Uuid,
+    pub operation: Operation,
+    pub attempt: u32,
+}
+
+// A middleware either calls `next` to continue down the chain, or returns its
+// own output to short-circuit the invocation.
+pub trait Middleware<O> {
+    fn handle(&self, invocation: &Invocation, next: &mut dyn FnMut() -> O) -> O;
+
+    // Async invocations come through here. Unless overridden, `handle`
+    // decides before any of the work is done, and calling `next` runs the
+    // rest of the invocation to completion there and then, holding up the
+    // futures polled alongside it; a middleware that waits instead overrides
+    // this.
+    fn handle_async<'f>(
+        &'f self,
+        invocation: &'f Invocation,
+        next: BoxFuture<'f, O>,
+    ) -> BoxFuture<'f, O>
+    where
+        O: 'f,
+    {
+        Box::pin(async move {
+            let mut next = Some(next);
+
+            self.handle(invocation, &mut || {
+                BlockingExecutor.block_on(
+                    next.take()
+                        .expect("middleware called `next` twice in an async invocation"),
+                )
+            })
+        })
+    }
+}
+
+impl<O, F> Middleware<O> for F
+where
+    F: Fn(&Invocation, &mut dyn FnMut() -> O) -> O,
+{
+    fn handle(&self, invocation: &Invocation, next: &mut dyn FnMut() -> O) -> O {
+        self(invocation, next)
+    }
+}
+
+pub struct Hooks<'a, O, M: Threading = Local> {
+    before: Option<Box<M::Before<'a>>>,
+    after: Option<Box<M::After<'a, O>>>,
+}
+
+impl<O> Hooks<'_, O> {
+    pub fn new() -> Self {
+        Self::new_in(Local)
+    }
+}
+
+impl<'a, O, M: Threading> Hooks<'a, O, M> {
+    pub fn new_in(_threading: M) -> Self {
+        Self {
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Invocation) + 'a,
+        M: Holds<F>,
+    {
+        self.before = Some(M::before(hook));
+
+        self
+    }
+
+    pub fn after<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Invocation, &O, Duration) + 'a,
+        M: Holds<F>,
+    {
+        self.after = Some(M::after(hook));
+
+        self
+    }
+}
+
+impl<O, M: Threading> Default for Hooks<'_, O, M> {
+    fn default() -> Self {
+        Self::new_in(M::default())
+    }
+}
+
+impl<O, M: Threading> Middleware<O> for Hooks<'_, O, M> {
+    fn handle(&self, invocation: &Invocation, next: &mut dyn FnMut() -> O) -> O {
+        if let Some(before) = &self.before {
+            before(invocation);
+        }
+
+        let start = Instant::now();
+        let result = next();
+
+        if let Some(after) = &self.after {
+            after(invocation, &result, start.elapsed());
+        }
+
+        result
+    }
+
+    fn handle_async<'f>(
+        &'f self,
+        invocation: &'f Invocation,
+        next: BoxFuture<'f, O>,
+    ) -> BoxFuture<'f, O>
+    where
+        O: 'f,
+    {
+        Box::pin(async move {
+            if let Some(before) = &self.before {
+                before(invocation);
+            }
+
+            let start = Instant::now();
+            let result = next.await;
+
+            if let Some(after) = &self.after {
+                after(invocation, &result, start.elapsed());
+            }
+
+            result
+        })
+    }
+}
+
+// Layers are shared, so cloning a chain is cheap.
+pub struct Chain<'a, O, M: Threading = Local> {
+    layers: Vec<Arc<M::Layer<'a, O>>>,
+}
+
+impl<O> Chain<'_, O> {
+    pub fn new() -> Self {
+        Self::new_in(Local)
+    }
+}
+
+impl<'a, O, M: Threading> Chain<'a, O, M> {
+    pub fn new_in(_threading: M) -> Self {
+        Self { layers: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    // The first middleware pushed is the outermost one.
+    pub fn push<F>(&mut self, middleware: F) -> &mut Self
+    where
+        F: Middleware<O> + 'a,
+        M: Holds<F>,
+    {
+        self.layers.push(M::layer(middleware));
+
+        self
+    }
+
+    pub fn clear(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn run(&self, invocation: &Invocation, mut f: impl FnMut() -> O) -> O {
+        self.run_from(0, invocation, &mut f)
+    }
+
+    pub fn run_async<'f>(
+        &'f self,
+        invocation: &'f Invocation,
+        future: BoxFuture<'f, O>,
+    ) -> BoxFuture<'f, O>
+    where
+        O: 'f,
+    {
+        self.layers
+            .iter()
+            .rev()
+            .fold(future, |next, layer| layer.handle_async(invocation, next))
+    }
+
+    fn run_from(&self, index: usize, invocation: &Invocation, f: &mut dyn FnMut() -> O) -> O {
+        match self.layers.get(index) {
+            Some(layer) => {
+                layer.handle(invocation, &mut || self.run_from(index + 1, invocation, f))
+            }
+            None => f(),
+        }
+    }
+}
+
+impl<O, M: Threading> Clone for Chain<'_, O, M> {
+    fn clone(&self) -> Self {
+        Self {
+            layers: self.layers.clone(),
+        }
+    }
+}
+
+impl<O, M: Threading> Default for Chain<'_, O, M> {
+    fn default() -> Self {
+        Self::new_in(M::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_chain() {
+        let log = RefCell::new(Vec::new());
+        let mut chain = Chain::new();
+
+        chain.push(
+            Hooks::new()
+                .before(|e| log.borrow_mut().push(format!("before {}", e.attempt)))
+                .after(|_, result, _| log.borrow_mut().push(format!("after {}", result))),
+        );
+        chain.push(|e: &Invocation, next: &mut dyn FnMut() -> i32| {
+            if e.attempt > 1 {
+                return -1;
+            }
+
+            next() * 10
+        });
+
+        let invocation = Invocation {
+            uuid: Uuid::new_v4(),
+            operation: Operation::Call,
+            attempt: 1,
+        };
+
+        assert_eq!(
+            20,
+            chain.run(&invocation, || {
+                log.borrow_mut().push("call".to_string());
+                2
+            })
+        );
+
+        let invocation = Invocation {
+            attempt: 2,
+            ..invocation
+        };
+
+        assert_eq!(-1, chain.run(&invocation, || unreachable!()));
+        assert_eq!(
+            vec!["before 1", "call", "after 20", "before 2", "after -1"],
+            *log.borrow()
+        );
+    }
+
+    #[test]
+    fn test_chain_async() {
+        let log = RefCell::new(Vec::new());
+        let mut chain = Chain::new();
+
+        chain.push(
+            Hooks::new()
+                .before(|_| log.borrow_mut().push("before".to_string()))
+                .after(|_, result, _| log.borrow_mut().push(format!("after {}", result))),
+        );
+        chain.push(|e: &Invocation, next: &mut dyn FnMut() -> i32| {
+            if e.attempt > 1 {
+                return -1;
+            }
+
+            next() * 10
+        });
+
+        let call = || {
+            Box::pin(async {
+                log.borrow_mut().push("call".to_string());
+                2
+            })
+        };
+        let invocation = Invocation {
+            uuid: Uuid::new_v4(),
+            operation: Operation::Call,
+            attempt: 1,
+        };
+
+        assert_eq!(
+            20,
+            BlockingExecutor.block_on(chain.run_async(&invocation, call()))
+        );
+
+        let invocation = Invocation {
+            attempt: 2,
+            ..invocation
+        };
+
+        // the closure turns the call away before it is ever polled
+        assert_eq!(
+            -1,
+            BlockingExecutor.block_on(chain.run_async(&invocation, call()))
+        );
+        assert_eq!(
+            vec!["before", "call", "after 20", "before", "after -1"],
+            *log.borrow()
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Local, Schedule, ScheduleError, Task, TaskManager, Threading};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskSnapshot<D> {
+    pub uuid: Uuid,
+    pub before: Option<Uuid>,
+    pub after: Option<Uuid>,
+    pub dependencies: Vec<Uuid>,
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub quota_group: Option<String>,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub not_before: Option<SystemTime>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub deadline: Option<SystemTime>,
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    #[serde(default)]
+    pub previous_run: Option<Uuid>,
+    #[serde(default)]
+    pub suspended: bool,
+    #[serde(default)]
+    pub source: Option<Uuid>,
+    pub called: u32,
+    pub data: D,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueSnapshot<D, O = ()> {
+    pub tasks: Vec<TaskSnapshot<D>>,
+    // outputs kept for the tasks reading them
+    #[serde(default = "Vec::new")]
+    pub results: Vec<(Uuid, O)>,
+    // tasks that did not succeed, as far as queued tasks depend on them
+    #[serde(default)]
+    pub failed: Vec<Uuid>,
+}
+
+#[derive(Debug)]
+pub enum PersistError {
+    Json(serde_json::Error),
+    UnknownKind(String),
+    Schedule(ScheduleError),
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "json error: {}", e),
+            Self::UnknownKind(kind) => write!(f, "unknown task kind: {}", kind),
+            Self::Schedule(e) => write!(f, "invalid schedule: {}", e),
+        }
+    }
+}
+
+impl Error for PersistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Json(e) => Some(e),
+            Self::UnknownKind(_) => None,
+            Self::Schedule(e) => Some(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for PersistError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<ScheduleError> for PersistError {
+    fn from(e: ScheduleError) -> Self {
+        Self::Schedule(e)
+    }
+}
+
+type Attach<T, U, R, E, M> = Box<dyn Fn(&mut Task<T, U, R, E, M>)>;
+
+pub struct TaskRegistry<T, U, R = (), E = (), M: Threading = Local> {
+    kinds: HashMap<String, Attach<T, U, R, E, M>>,
+}
+
+impl<T, U, R, E> TaskRegistry<T, U, R, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskRegistry<T, U, R, E, M> {
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        attach: impl Fn(&mut Task<T, U, R, E, M>) + 'static,
+    ) -> &mut Self {
+        self.kinds.insert(kind.into(), Box::new(attach));
+
+        self
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.kinds.contains_key(kind)
+    }
+
+    pub fn attach(&self, task: &mut Task<T, U, R, E, M>) -> Result<(), PersistError> {
+        if let Some(kind) = task.kind() {
+            let attach = self
+                .kinds
+                .get(kind)
+                .ok_or_else(|| PersistError::UnknownKind(kind.to_string()))?;
+
+            attach(task);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, U, R, E, M: Threading> Default for TaskRegistry<T, U, R, E, M> {
+    fn default() -> Self {
+        Self {
+            kinds: HashMap::new(),
+        }
+    }
+}
+
+impl<T, U, R, E, M: Threading> Task<T, U, R, E, M> {
+    pub fn snapshot(&self) -> TaskSnapshot<&T> {
+        TaskSnapshot {
+            uuid: self.uuid,
+            before: self.before,
+            after: self.after,
+            dependencies: self.dependencies.clone(),
+            kind: self.kind.clone(),
+            quota_group: self.quota_group.clone(),
+            key: self.key.clone(),
+            not_before: self.not_before,
+            priority: self.priority,
+            deadline: self.deadline,
+            timeout: self.timeout,
+            schedule: self.schedule.clone(),
+            previous_run: self.previous_run,
+            suspended: self.suspended,
+            source: self.source,
+            called: self.called,
+            data: &self.data,
+        }
+    }
+
+    pub fn restore(
+        snapshot: TaskSnapshot<T>,
+        registry: &TaskRegistry<T, U, R, E, M>,
+    ) -> Result<Self, PersistError>
+    where
+        R: Default,
+    {
+        let mut task = Task::new_in(snapshot.data, M::default());
+
+        task.uuid = snapshot.uuid;
+        task.before = snapshot.before;
+        task.after = snapshot.after;
+        task.dependencies = snapshot.dependencies;
+        task.kind = snapshot.kind;
+        task.quota_group = snapshot.quota_group;
+        task.key = snapshot.key;
+        task.not_before = snapshot.not_before;
+        task.priority = snapshot.priority;
+        task.deadline = snapshot.deadline;
+        task.timeout = snapshot.timeout;
+        task.set_schedule(snapshot.schedule)?;
+        task.previous_run = snapshot.previous_run;
+        task.suspended = snapshot.suspended;
+        task.source = snapshot.source;
+        task.called = snapshot.called;
+
+        registry.attach(&mut task)?;
+
+        Ok(task)
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    pub fn snapshot(&self) -> QueueSnapshot<&T, &R> {
+        let mut results = self
+            .results
+            .iter()
+            .map(|(k, v)| (*k, v))
+            .collect::<Vec<_>>();
+        let mut failed = self
+            .tasks
+            .iter()
+            .flat_map(|e| e.prerequisites())
+            .filter(|e| self.failed.contains(e))
+            .copied()
+            .collect::<Vec<_>>();
+
+        // in a fixed order, so equal queues give equal snapshots
+        results.sort_by_key(|e| e.0);
+        failed.sort();
+        failed.dedup();
+
+        QueueSnapshot {
+            tasks: self.tasks.iter().map(|e| e.snapshot()).collect(),
+            results,
+            failed,
+        }
+    }
+
+    // A task whose source ran before the snapshot but whose output is not in
+    // it can never get its input, so it goes to the dead letters.
+    pub fn restore(
+        snapshot: QueueSnapshot<T, R>,
+        registry: &TaskRegistry<T, U, R, E, M>,
+    ) -> Result<Self, PersistError>
+    where
+        R: Default + Clone,
+    {
+        let mut manager = Self::new_in(M::default());
+
+        for task in snapshot.tasks {
+            manager.tasks.push_back(Task::restore(task, registry)?);
+        }
+
+        manager.results.extend(snapshot.results);
+        manager.failed.extend(snapshot.failed);
+
+        let lost = manager
+            .tasks
+            .iter()
+            .filter_map(|e| e.source)
+            .filter(|e| !manager.contains(*e) && !manager.results.contains_key(e))
+            .collect::<Vec<_>>();
+
+        manager.failed.extend(lost);
+
+        if !manager.results.is_empty() || manager.tasks.iter().any(|e| e.source.is_some()) {
+            manager.clone_result = Some(R::clone);
+        }
+
+        Ok(manager)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error>
+    where
+        T: Serialize,
+        R: Serialize,
+    {
+        serde_json::to_string(&self.snapshot())
+    }
+
+    pub fn from_json(
+        json: &str,
+        registry: &TaskRegistry<T, U, R, E, M>,
+    ) -> Result<Self, PersistError>
+    where
+        T: DeserializeOwned,
+        R: DeserializeOwned + Default + Clone,
+    {
+        Self::restore(serde_json::from_str(json)?, registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> TaskRegistry<i32, i32, i32, String> {
+        let mut registry = TaskRegistry::new();
+
+        registry.register("add", |task| {
+            task.set_callback(|this, arg| {
+                this.data += *arg;
+
+                Ok(this.data)
+            });
+        });
+        registry.register("reject", |task| {
+            task.set_callback(|_, _| Err("rejected".to_string()));
+            task.set_dry_run_callback(|_, _| false);
+        });
+
+        registry
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let registry = registry();
+        let mut manager = TaskManager::new();
+
+        let mut task1 = Task::new(1);
+        task1.set_kind("add");
+        registry.attach(&mut task1).unwrap();
+        assert_eq!(Ok(2), task1.call(1));
+        assert_eq!(&2, task1.data());
+
+        let mut task2 = Task::new(2);
+        task2.set_kind("reject");
+
+        let task3 = Task::new(3);
+
+        let uuids = [task1.uuid(), task2.uuid(), task3.uuid()];
+
+        manager.push(task1);
+        manager.push(task2);
+        manager.push(task3);
+        manager.add_dependency(uuids[0], uuids[2]).unwrap();
+
+        let json = manager.to_json().unwrap();
+        let mut restored = TaskManager::from_json(&json, &registry).unwrap();
+
+        assert_eq!(manager.snapshot(), restored.snapshot());
+        assert_eq!(3, restored.len());
+
+        let outcome = restored.pop_and_call(1).unwrap();
+        let task = outcome.task.unwrap();
+        assert_eq!(uuids[1], task.uuid());
+        assert_eq!(Err("rejected".to_string()), outcome.result);
+        assert!(!task.dry_run(1));
+
+        let outcome = restored.pop_and_call(1).unwrap();
+        assert_eq!(uuids[2], outcome.uuid);
+        assert_eq!(Ok(0), outcome.result);
+
+        let outcome = restored.pop_and_call(5).unwrap();
+        let task = outcome.task.unwrap();
+        assert_eq!(Ok(7), outcome.result);
+        assert_eq!(uuids[0], task.uuid());
+        assert_eq!(&7, task.data());
+        assert_eq!(2, task.called());
+    }
+
+    #[test]
+    fn test_round_trip_outputs() {
+        let mut registry = registry();
+        let mut manager = TaskManager::new();
+
+        registry.register("sum", |task| {
+            task.set_callback(|this, _| Ok(this.input().copied().unwrap_or(0) + this.data));
+        });
+
+        let mut tasks =
+            [("add", 2), ("sum", 0), ("reject", 1), ("add", 0)].map(|(kind, priority)| {
+                let mut task = Task::new(10);
+                task.set_kind(kind);
+                task.set_priority(priority);
+                registry.attach(&mut task).unwrap();
+                task
+            });
+        let uuids = tasks.each_ref().map(|e| e.uuid());
+
+        tasks[3].add_dependency(uuids[2]);
+
+        for task in tasks {
+            match task.kind() {
+                Some("sum") => {
+                    manager.push_piped(task).unwrap();
+                }
+                _ => manager.push(task),
+            }
+        }
+
+        manager.pop_and_call(1).unwrap();
+        manager.pop_and_call(1).unwrap();
+
+        // the output waiting to be read and the failure are both kept
+        let json = manager.to_json().unwrap();
+        let mut restored = TaskManager::from_json(&json, &registry).unwrap();
+
+        assert_eq!(manager.snapshot(), restored.snapshot());
+        assert_eq!(vec![(uuids[1], Ok(21))], restored.call_all(&1).outcomes);
+        assert_eq!(uuids[3], restored.dead_letters()[0].uuid());
+    }
+
+    #[test]
+    fn test_unknown_kind() {
+        let mut manager = TaskManager::<_, i32, i32, String>::new();
+
+        let mut task = Task::new(1);
+        task.set_kind("missing");
+        manager.push(task);
+
+        let json = manager.to_json().unwrap();
+
+        match TaskManager::from_json(&json, &registry()) {
+            Err(PersistError::UnknownKind(kind)) => assert_eq!("missing", kind),
+            _ => panic!("expected an unknown kind error"),
+        }
+
+        assert!(matches!(
+            TaskManager::from_json("{", &registry()),
+            Err(PersistError::Json(_))
+        ));
+    }
+}
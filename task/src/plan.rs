@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{GraphError, TaskManager, Threading};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    // a dependency is predicted to fail
+    Blocked,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Blocked => "blocked",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanEntry {
+    pub position: usize,
+    pub uuid: Uuid,
+    pub kind: Option<String>,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Plan {
+    pub entries: Vec<PlanEntry>,
+}
+
+impl Plan {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn will_succeed(&self) -> bool {
+        self.entries.iter().all(|e| e.outcome == Outcome::Success)
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.entries.iter().filter(|e| e.outcome == outcome).count()
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:>4}  {}  {:<8}  {}",
+                entry.position,
+                entry.uuid,
+                entry.outcome,
+                entry.kind.as_deref().unwrap_or("-")
+            )?;
+        }
+
+        write!(
+            f,
+            "{} tasks: {} success, {} failure, {} blocked",
+            self.len(),
+            self.count(Outcome::Success),
+            self.count(Outcome::Failure),
+            self.count(Outcome::Blocked)
+        )
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    pub fn plan(&self, data: &U) -> Result<Plan, GraphError> {
+        let order = self.execution_order()?;
+        let mut outcomes = HashMap::new();
+        let mut plan = Plan::default();
+
+        for (position, uuid) in order.into_iter().enumerate() {
+            let task = &self.tasks[self.position(uuid).unwrap()];
+
+            let blocked = task
+                .dependencies()
+                .iter()
+                .any(|e| outcomes.get(e).is_some_and(|e| *e != Outcome::Success));
+
+            let outcome = if blocked {
+                Outcome::Blocked
+            } else if (task.do_dry_run)(task, data) {
+                Outcome::Success
+            } else {
+                Outcome::Failure
+            };
+
+            outcomes.insert(uuid, outcome);
+
+            plan.entries.push(PlanEntry {
+                position,
+                uuid,
+                kind: task.kind().map(str::to_string),
+                outcome,
+            });
+        }
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Task;
+
+    #[test]
+    fn test_plan() {
+        let mut manager = TaskManager::<_, _>::new();
+
+        let mut fetch = Task::new(1);
+        fetch.set_kind("fetch");
+        fetch.set_dry_run_callback(|this, arg| this.data() < arg);
+
+        let mut upload = Task::new(2);
+        upload.set_kind("upload");
+
+        let mut cleanup = Task::new(3);
+        cleanup.set_dry_run_callback(|this, arg| this.data() < arg);
+
+        let uuids = [fetch.uuid(), upload.uuid(), cleanup.uuid()];
+
+        manager.push(upload);
+        manager.push(fetch);
+        manager.push(cleanup);
+        manager.add_dependency(uuids[1], uuids[0]).unwrap();
+
+        let plan = manager.plan(&2).unwrap();
+
+        assert_eq!(
+            vec![
+                (0, uuids[0], Outcome::Success),
+                (1, uuids[1], Outcome::Success),
+                (2, uuids[2], Outcome::Failure),
+            ],
+            plan.entries
+                .iter()
+                .map(|e| (e.position, e.uuid, e.outcome))
+                .collect::<Vec<_>>()
+        );
+        assert!(!plan.will_succeed());
+
+        let plan = manager.plan(&1).unwrap();
+
+        assert_eq!(Outcome::Failure, plan.entries[0].outcome);
+        assert_eq!(Outcome::Blocked, plan.entries[1].outcome);
+        assert_eq!(Some("upload"), plan.entries[1].kind.as_deref());
+
+        let text = plan.to_string();
+        assert!(text.contains(&format!("   1  {}  blocked   upload", uuids[1])));
+        assert!(text.ends_with("3 tasks: 0 success, 2 failure, 1 blocked"));
+
+        let json = plan.to_json().unwrap();
+        assert!(json.contains(r#""outcome":"blocked""#));
+        assert_eq!(plan, serde_json::from_str(&json).unwrap());
+
+        // planning must not touch the queue
+        assert_eq!(3, manager.len());
+        assert!(manager.tasks.iter().all(|e| e.called() == 0));
+    }
+}
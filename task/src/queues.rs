@@ -0,0 +1,349 @@
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::{CallOutcome, Holds, Local, PushError, Summary, Task, TaskManager, Threading};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueError {
+    UnknownQueue(String),
+    // no route matched and there is no default queue
+    Unrouted,
+    Rejected(PushError),
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownQueue(name) => write!(f, "unknown queue: {}", name),
+            Self::Unrouted => write!(f, "no queue for task"),
+            Self::Rejected(e) => write!(f, "task rejected: {}", e),
+        }
+    }
+}
+
+impl Error for QueueError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Rejected(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PushError> for QueueError {
+    fn from(value: PushError) -> Self {
+        Self::Rejected(value)
+    }
+}
+
+struct Queue<T, U, R, E, M: Threading> {
+    name: String,
+    manager: TaskManager<T, U, R, E, M>,
+    weight: u32,
+    // smooth weighted round robin: the queue with the most credit goes next
+    credit: i64,
+}
+
+// Named queues, each its own `TaskManager`, with tasks routed to them by
+// their data. Queues take turns in proportion to their weights, so a flood
+// in one cannot starve the others.
+pub struct QueueRegistry<T, U, R = (), E = (), M: Threading = Local> {
+    queues: Vec<Queue<T, U, R, E, M>>,
+    routes: Vec<(Box<M::Route<T>>, String)>,
+    default_queue: Option<String>,
+}
+
+impl<T, U, R, E> QueueRegistry<T, U, R, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, U, R, E, M: Threading> QueueRegistry<T, U, R, E, M> {
+    // Replaces a queue of the same name, handing back its manager. A queue
+    // with a weight of zero is never popped from.
+    pub fn add_queue(
+        &mut self,
+        name: impl Into<String>,
+        manager: TaskManager<T, U, R, E, M>,
+        weight: u32,
+    ) -> Option<TaskManager<T, U, R, E, M>> {
+        let name = name.into();
+        let queue = Queue {
+            name: name.clone(),
+            manager,
+            weight,
+            credit: 0,
+        };
+
+        match self.position(&name) {
+            Some(i) => Some(std::mem::replace(&mut self.queues[i], queue).manager),
+            None => {
+                self.queues.push(queue);
+                None
+            }
+        }
+    }
+
+    pub fn remove_queue(&mut self, name: &str) -> Option<TaskManager<T, U, R, E, M>> {
+        let index = self.position(name)?;
+
+        Some(self.queues.remove(index).manager)
+    }
+
+    pub fn queue(&self, name: &str) -> Option<&TaskManager<T, U, R, E, M>> {
+        self.queues.get(self.position(name)?).map(|e| &e.manager)
+    }
+
+    pub fn queue_mut(&mut self, name: &str) -> Option<&mut TaskManager<T, U, R, E, M>> {
+        let index = self.position(name)?;
+
+        self.queues.get_mut(index).map(|e| &mut e.manager)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queues.iter().map(|e| e.name.as_str())
+    }
+
+    pub fn weight(&self, name: &str) -> Option<u32> {
+        self.queues.get(self.position(name)?).map(|e| e.weight)
+    }
+
+    pub fn set_weight(&mut self, name: &str, weight: u32) -> bool {
+        match self.position(name) {
+            Some(i) => {
+                self.queues[i].weight = weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Routes are tried in the order they were added; the first one matching
+    // a task's data picks its queue.
+    pub fn route<F>(&mut self, queue: impl Into<String>, predicate: F)
+    where
+        F: Fn(&T) -> bool + 'static,
+        M: Holds<F>,
+    {
+        self.routes.push((M::route(predicate), queue.into()));
+    }
+
+    // Where tasks go that no route matches.
+    pub fn set_default_queue(&mut self, queue: Option<String>) {
+        self.default_queue = queue;
+    }
+
+    pub fn route_of(&self, data: &T) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|(predicate, _)| predicate(data))
+            .map(|(_, queue)| queue.as_str())
+            .or(self.default_queue.as_deref())
+    }
+
+    pub fn push(&mut self, task: Task<T, U, R, E, M>) -> Result<Uuid, QueueError> {
+        let name = self.route_of(task.data()).ok_or(QueueError::Unrouted)?;
+        let index = self
+            .position(name)
+            .ok_or_else(|| QueueError::UnknownQueue(name.to_string()))?;
+
+        Ok(self.queues[index].manager.try_push(task)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(|e| e.manager.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(|e| e.manager.is_empty())
+    }
+
+    pub fn pop(&mut self) -> Option<Task<T, U, R, E, M>> {
+        self.next(|e| e.pop())
+    }
+
+    pub fn pop_and_call(&mut self, data: &U) -> Option<CallOutcome<T, U, R, E, M>> {
+        self.next(|e| e.call_next(data))
+    }
+
+    pub fn call_all(&mut self, data: &U) -> Summary<R, E> {
+        let mut summary = Summary {
+            outcomes: Vec::new(),
+        };
+
+        while let Some(outcome) = self.pop_and_call(data) {
+            summary.outcomes.push((outcome.uuid, outcome.result));
+        }
+
+        summary
+    }
+
+    // Every queue with a task ready to go earns its weight in credit and the
+    // one with the most goes first, paying back what all of them earned. A
+    // queue with nothing ready earns nothing, so one that was paused or held
+    // back does not return with a run of turns saved up.
+    fn next<X>(
+        &mut self,
+        f: impl FnOnce(&mut TaskManager<T, U, R, E, M>) -> Option<X>,
+    ) -> Option<X> {
+        let mut candidates = Vec::new();
+
+        for (i, queue) in self.queues.iter_mut().enumerate() {
+            if queue.weight > 0 && queue.manager.next_ready().is_some() {
+                candidates.push(i);
+            }
+        }
+
+        let total = candidates
+            .iter()
+            .map(|e| self.queues[*e].weight as i64)
+            .sum::<i64>();
+
+        for i in &candidates {
+            self.queues[*i].credit += self.queues[*i].weight as i64;
+        }
+
+        // ties go to the queue added first
+        let i = candidates
+            .into_iter()
+            .min_by_key(|e| (Reverse(self.queues[*e].credit), *e))?;
+
+        self.queues[i].credit -= total;
+
+        f(&mut self.queues[i].manager)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.queues.iter().position(|e| e.name == name)
+    }
+}
+
+impl<T, U, R, E, M: Threading> Default for QueueRegistry<T, U, R, E, M> {
+    fn default() -> Self {
+        Self {
+            queues: Vec::new(),
+            routes: Vec::new(),
+            default_queue: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(bulk: u32, urgent: u32) -> QueueRegistry<i32, ()> {
+        let mut registry = QueueRegistry::new();
+
+        registry.add_queue("bulk", TaskManager::new(), bulk);
+        registry.add_queue("urgent", TaskManager::new(), urgent);
+        registry.route("urgent", |data: &i32| *data < 0);
+        registry.set_default_queue(Some("bulk".to_string()));
+
+        registry
+    }
+
+    #[test]
+    fn test_route() {
+        let mut registry = registry(1, 1);
+
+        registry.push(Task::new(1)).unwrap();
+        registry.push(Task::new(-1)).unwrap();
+        registry.route("missing", |data: &i32| *data == 0);
+
+        assert_eq!(
+            Err(QueueError::UnknownQueue("missing".to_string())),
+            registry.push(Task::new(0))
+        );
+        assert_eq!(1, registry.queue("bulk").unwrap().len());
+        assert_eq!(1, registry.queue("urgent").unwrap().len());
+
+        registry.set_default_queue(None);
+
+        assert_eq!(Err(QueueError::Unrouted), registry.push(Task::new(2)));
+        assert_eq!(2, registry.len());
+
+        // a local registry takes routes that cannot cross threads
+        let limit = std::rc::Rc::new(std::cell::Cell::new(10));
+        let route_limit = limit.clone();
+
+        registry.route("bulk", move |data: &i32| *data < route_limit.get());
+
+        assert!(registry.push(Task::new(2)).is_ok());
+
+        limit.set(0);
+
+        assert_eq!(Err(QueueError::Unrouted), registry.push(Task::new(3)));
+    }
+
+    #[test]
+    fn test_weighted_pop() {
+        let mut registry = registry(2, 1);
+
+        for i in 1..=6 {
+            registry.push(Task::new(i)).unwrap();
+        }
+
+        for i in 1..=3 {
+            registry.push(Task::new(-i)).unwrap();
+        }
+
+        let order = (0..9)
+            .map(|_| *registry.pop().unwrap().data())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![1, -1, 2, 3, -2, 4, 5, -3, 6], order);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_paused_queue() {
+        let mut registry = registry(1, 1);
+
+        registry.push(Task::new(1)).unwrap();
+        registry.push(Task::new(2)).unwrap();
+        registry.push(Task::new(-1)).unwrap();
+        registry.queue_mut("urgent").unwrap().pause();
+
+        // the paused queue is passed over rather than blocking the others
+        assert_eq!(2, registry.call_all(&()).len());
+        assert_eq!(1, registry.len());
+
+        registry.queue_mut("urgent").unwrap().resume();
+
+        assert_eq!(Some(&-1), registry.pop().as_ref().map(|e| e.data()));
+    }
+
+    #[test]
+    fn test_resume_interleaves() {
+        let mut registry = registry(1, 1);
+
+        for i in 1..=6 {
+            registry.push(Task::new(i)).unwrap();
+        }
+
+        for i in 1..=3 {
+            registry.push(Task::new(-i)).unwrap();
+        }
+
+        registry.queue_mut("urgent").unwrap().pause();
+
+        let paused = (0..3)
+            .map(|_| *registry.pop().unwrap().data())
+            .collect::<Vec<_>>();
+
+        registry.queue_mut("urgent").unwrap().resume();
+
+        let resumed = (0..6)
+            .map(|_| *registry.pop().unwrap().data())
+            .collect::<Vec<_>>();
+
+        // the urgent queue saved up no credit while it was paused
+        assert_eq!(vec![1, 2, 3], paused);
+        assert_eq!(vec![4, -1, 5, -2, 6, -3], resumed);
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::{Duration, SystemTime};
+
+use uuid::Uuid;
+
+use crate::{Task, TaskManager, Threading};
+
+// Limits shared by every task in one quota group: a token bucket, where each call
+// takes a token, and a cap on how many run at once. A task over its quota is
+// skipped and the next eligible one is taken instead.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Quota {
+    rate: Option<Rate>,
+    max_in_flight: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rate {
+    capacity: u32,
+    refill: Duration,
+    tokens: u32,
+    refilled_at: Option<SystemTime>,
+}
+
+impl Quota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The bucket starts full and gains a token every `refill`, up to
+    // `capacity`.
+    pub fn with_rate(mut self, capacity: u32, refill: Duration) -> Self {
+        let capacity = capacity.max(1);
+
+        self.rate = Some(Rate {
+            capacity,
+            refill: refill.max(Duration::from_nanos(1)),
+            tokens: capacity,
+            refilled_at: None,
+        });
+
+        self
+    }
+
+    // Tasks taken with `acquire` count as in flight until they are handed back
+    // through `finish` or `release`.
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = Some(max);
+
+        self
+    }
+
+    pub fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    // Tokens left as of the last refill; `None` without a rate.
+    pub fn tokens(&self) -> Option<u32> {
+        self.rate.as_ref().map(|e| e.tokens)
+    }
+
+    fn refill(&mut self, now: SystemTime) {
+        let Some(rate) = &mut self.rate else {
+            return;
+        };
+
+        let Some(refilled_at) = rate.refilled_at else {
+            rate.refilled_at = Some(now);
+            return;
+        };
+
+        let elapsed = now.duration_since(refilled_at).unwrap_or_default();
+        let added = elapsed.as_nanos() / rate.refill.as_nanos();
+
+        if rate.tokens as u128 + added >= rate.capacity as u128 {
+            rate.tokens = rate.capacity;
+            rate.refilled_at = Some(now);
+        } else {
+            rate.tokens += added as u32;
+            rate.refilled_at = Some(refilled_at + rate.refill * added as u32);
+        }
+    }
+
+    fn allows(&self, in_flight: usize) -> bool {
+        self.rate.as_ref().is_none_or(|e| e.tokens > 0)
+            && self.max_in_flight.is_none_or(|e| in_flight < e)
+    }
+
+    fn take_token(&mut self) {
+        if let Some(rate) = &mut self.rate {
+            rate.tokens = rate.tokens.saturating_sub(1);
+        }
+    }
+
+    // When an empty bucket gets its next token.
+    fn next_token(&self) -> Option<SystemTime> {
+        let rate = self.rate.as_ref().filter(|e| e.tokens == 0)?;
+
+        Some(rate.refilled_at? + rate.refill)
+    }
+}
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    pub fn set_quota(&mut self, group: impl Into<String>, quota: Quota) {
+        self.quotas.insert(group.into(), quota);
+    }
+
+    pub fn quota(&self, group: &str) -> Option<&Quota> {
+        self.quotas.get(group)
+    }
+
+    pub fn remove_quota(&mut self, group: &str) -> Option<Quota> {
+        self.quotas.remove(group)
+    }
+
+    pub fn in_flight(&self, group: &str) -> usize {
+        self.running
+            .values()
+            .filter(|e| e.quota_group.as_deref() == Some(group))
+            .count()
+    }
+
+    pub(crate) fn refill_quotas(&mut self) {
+        let now = self.clock.now();
+
+        for quota in self.quotas.values_mut() {
+            quota.refill(now);
+        }
+    }
+
+    pub(crate) fn within_quota(&self, task: &Task<T, U, R, E, M>) -> bool {
+        match task
+            .quota_group()
+            .and_then(|e| self.quotas.get(e).map(|quota| (e, quota)))
+        {
+            Some((group, quota)) => quota.allows(self.in_flight(group)),
+            None => true,
+        }
+    }
+
+    pub(crate) fn take_quota(&mut self, task: &Task<T, U, R, E, M>) {
+        if let Some(quota) = task.quota_group().and_then(|e| self.quotas.get_mut(e)) {
+            quota.take_token();
+        }
+    }
+
+    // Removes a queued task if its quota allows it to run now.
+    pub(crate) fn admit(&mut self, uuid: Uuid) -> Option<Task<T, U, R, E, M>> {
+        if !self.get(uuid).is_some_and(|e| self.within_quota(e)) {
+            return None;
+        }
+
+        let task = self.remove(uuid)?;
+        self.take_quota(&task);
+
+        Some(task)
+    }
+
+    // When a queued task held back by an empty bucket could run again.
+    pub(crate) fn quota_wake(&self) -> Option<SystemTime> {
+        self.tasks
+            .iter()
+            .filter_map(|e| self.quotas.get(e.quota_group()?))
+            .filter_map(|e| e.next_token())
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_rate() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let mut manager = TaskManager::<i32, ()>::with_clock(clock.clone());
+
+        manager.set_quota("api", Quota::new().with_rate(2, Duration::from_secs(10)));
+
+        for i in 0..4 {
+            let mut task = Task::new(i);
+            task.set_quota_group("api");
+            manager.push(task);
+        }
+
+        manager.push(Task::new(4));
+
+        // the untagged task is not held back by the empty bucket
+        let popped = (0..4)
+            .map_while(|_| manager.pop())
+            .map(|e| *e.data())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec![0, 1, 4], popped);
+        assert_eq!(Some(0), manager.quota("api").unwrap().tokens());
+        assert_eq!(
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10)),
+            manager.next_wake()
+        );
+
+        clock.advance(Duration::from_secs(15));
+
+        assert_eq!(&2, manager.pop().unwrap().data());
+        assert!(manager.pop().is_none());
+
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(&3, manager.pop().unwrap().data());
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_max_in_flight() {
+        let mut manager = TaskManager::<i32, ()>::new();
+
+        manager.set_quota("db", Quota::new().with_max_in_flight(1));
+
+        for i in 0..3 {
+            let mut task = Task::new(i);
+
+            if i < 2 {
+                task.set_quota_group("db");
+            }
+
+            manager.push(task);
+        }
+
+        let (first, _) = manager.start_call().unwrap();
+
+        assert_eq!(1, manager.in_flight("db"));
+
+        let (second, _) = manager.start_call().unwrap();
+
+        assert_eq!(&2, second.data());
+        assert!(manager.start_call().is_none());
+
+        manager.finish_call(first, Ok(()));
+
+        assert_eq!(0, manager.in_flight("db"));
+
+        // an acquired task holds its place until it is handed back
+        let popped = manager.acquire().unwrap();
+
+        assert_eq!(&1, popped.data());
+        assert_eq!(1, manager.in_flight("db"));
+
+        let mut task = Task::new(3);
+        task.set_quota_group("db");
+        manager.push(task);
+
+        assert!(manager.acquire().is_none());
+
+        manager.finish(popped, Ok(()));
+
+        assert_eq!(&3, manager.acquire().unwrap().data());
+    }
+}
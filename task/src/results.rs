@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use crate::{GraphError, PushError, Task, TaskManager, Threading};
+
+impl<T, U, R, E, M: Threading> TaskManager<T, U, R, E, M> {
+    // From now on the output of every successful call is kept, by task UUID,
+    // until it is taken or cleared. Otherwise only piped outputs are kept,
+    // until the tasks reading them are done.
+    pub fn keep_results(&mut self)
+    where
+        R: Clone,
+    {
+        self.clone_result = Some(R::clone);
+        self.keep_results = true;
+    }
+
+    pub fn result(&self, uuid: Uuid) -> Option<&R> {
+        self.results.get(&uuid)
+    }
+
+    pub fn take_result(&mut self, uuid: Uuid) -> Option<R> {
+        self.results.remove(&uuid)
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = (&Uuid, &R)> {
+        self.results.iter()
+    }
+
+    pub fn clear_results(&mut self) {
+        self.results.clear();
+    }
+
+    // `to` runs after `from` and gets its output as input. `from` may also
+    // be a task that already ran, as long as its result was kept.
+    pub fn pipe(&mut self, from: Uuid, to: Uuid) -> Result<(), GraphError>
+    where
+        R: Clone,
+    {
+        if !self.contains(to) {
+            return Err(GraphError::UnknownTask(to));
+        }
+
+        if !self.results.contains_key(&from) {
+            self.add_dependency(to, from)?;
+        }
+
+        self.get_mut(to).unwrap().set_source(Some(from));
+        self.clone_result = Some(R::clone);
+
+        Ok(())
+    }
+
+    // Queues `task` to take the output of the task queued before it, the
+    // one it gets linked to through `before`.
+    pub fn push_piped(&mut self, mut task: Task<T, U, R, E, M>) -> Result<Uuid, PushError>
+    where
+        R: Clone,
+    {
+        let source = self.tasks.back().ok_or(PushError::NoSource)?.uuid();
+
+        task.add_dependency(source);
+        task.set_source(Some(source));
+        self.clone_result = Some(R::clone);
+
+        self.try_push(task)
+    }
+
+    pub(crate) fn attach_input(&self, task: &mut Task<T, U, R, E, M>) {
+        if let (Some(source), Some(clone)) = (task.source, self.clone_result) {
+            task.input = self.results.get(&source).map(clone);
+        }
+    }
+
+    pub(crate) fn record_result(&mut self, uuid: Uuid, result: &Result<R, E>) {
+        if let (Ok(result), Some(clone)) = (result, self.clone_result) {
+            if self.keep_results || self.reads(uuid) {
+                self.results.insert(uuid, clone(result));
+            }
+        }
+    }
+
+    // Unless every result is kept, drops the ones no queued or running task
+    // is going to read anymore.
+    pub(crate) fn release_results(&mut self) {
+        if self.keep_results || self.results.is_empty() {
+            return;
+        }
+
+        let reads = self
+            .tasks
+            .iter()
+            .filter_map(|e| e.source)
+            .chain(self.running.values().filter_map(|e| e.source))
+            .collect::<HashSet<_>>();
+
+        self.results.retain(|uuid, _| reads.contains(uuid));
+    }
+
+    pub(crate) fn reads(&self, uuid: Uuid) -> bool {
+        self.tasks.iter().any(|e| e.source == Some(uuid))
+            || self.running.values().any(|e| e.source == Some(uuid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(name: &'static str) -> Task<(), (), String, String> {
+        let mut task = Task::new(());
+
+        task.set_callback(move |this, _| match this.input() {
+            Some(input) => Ok(format!("{} {}", input, name)),
+            None => Ok(name.to_string()),
+        });
+
+        task
+    }
+
+    #[test]
+    fn test_push_piped() {
+        let mut manager = TaskManager::new();
+        let (fetch, mut transform, upload) = (stage("fetch"), stage("transform"), stage("upload"));
+        let uuids = [fetch.uuid(), transform.uuid(), upload.uuid()];
+
+        // the dependency keeps it from running before its source
+        transform.set_priority(10);
+
+        assert_eq!(
+            Err(PushError::NoSource),
+            manager.push_piped(stage("nothing to read"))
+        );
+
+        manager.push(fetch);
+        manager.push_piped(transform).unwrap();
+        manager.push_piped(upload).unwrap();
+
+        let summary = manager.call_all(&());
+
+        assert_eq!(
+            vec![
+                (uuids[0], Ok("fetch".to_string())),
+                (uuids[1], Ok("fetch transform".to_string())),
+                (uuids[2], Ok("fetch transform upload".to_string())),
+            ],
+            summary.outcomes
+        );
+        // handed over results are let go once read
+        assert_eq!(0, manager.results().count());
+    }
+
+    #[test]
+    fn test_failed_source() {
+        let mut manager = TaskManager::new();
+        let mut fetch = stage("fetch");
+        let upload = stage("upload");
+        let b = upload.uuid();
+
+        fetch.set_callback(|_, _| Err("offline".to_string()));
+        manager.push(fetch);
+        manager.push_piped(upload).unwrap();
+
+        // rather than running without its input
+        assert_eq!(1, manager.call_all(&()).len());
+        assert_eq!(b, manager.dead_letters()[0].uuid());
+    }
+
+    #[test]
+    fn test_pop_piped() {
+        let mut manager = TaskManager::new();
+        let upload = stage("upload");
+        let b = upload.uuid();
+
+        manager.push(stage("fetch"));
+        manager.push_piped(upload).unwrap();
+
+        let fetch = manager.acquire().unwrap();
+
+        // held back until its source is handed back with its output
+        assert!(manager.acquire().is_none());
+
+        manager.finish(fetch, Ok("fetch".to_string()));
+
+        let upload = manager.acquire().unwrap();
+
+        assert_eq!(b, upload.uuid());
+        assert_eq!(Some(&"fetch".to_string()), upload.input());
+
+        // released without an output, so there is nothing to read
+        manager.push(stage("fetch"));
+        manager.push_piped(stage("upload")).unwrap();
+
+        let fetch = manager.acquire().unwrap();
+
+        manager.release(fetch.uuid());
+
+        assert!(manager.acquire().is_none());
+        assert_eq!(1, manager.dead_letters().len());
+
+        // nor is there for a source popped for good
+        manager.push(stage("fetch"));
+        manager.push_piped(stage("upload")).unwrap();
+        manager.pop();
+
+        assert!(manager.pop().is_none());
+        assert_eq!(2, manager.dead_letters().len());
+    }
+
+    #[test]
+    fn test_pipe() {
+        let mut manager = TaskManager::new();
+        let (fetch, upload) = (stage("fetch"), stage("upload"));
+        let (a, b) = (fetch.uuid(), upload.uuid());
+
+        assert!(manager.pipe(a, b).is_err());
+
+        manager.keep_results();
+        manager.push(fetch);
+        manager.call_all(&());
+        manager.push(upload);
+
+        // the source already ran, so only its kept result is needed
+        manager.pipe(a, b).unwrap();
+
+        assert!(manager.get(b).unwrap().dependencies().is_empty());
+
+        let outcome = manager.pop_and_call(()).unwrap();
+
+        assert_eq!(Ok("fetch upload".to_string()), outcome.result);
+        assert_eq!(Some(&"fetch".to_string()), outcome.task.unwrap().input());
+        assert_eq!(Some("fetch upload".to_string()), manager.take_result(b));
+        assert_eq!(None, manager.result(b));
+    }
+}
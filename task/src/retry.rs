@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+    None,
+    Fixed(Duration),
+    Exponential {
+        initial: Duration,
+        factor: f64,
+        max: Duration,
+    },
+}
+
+type Predicate<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+pub struct RetryPolicy<E = ()> {
+    max_attempts: u32,
+    backoff: Backoff,
+    jitter: f64,
+    predicate: Option<Predicate<E>>,
+}
+
+impl<E> RetryPolicy<E> {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            backoff: Backoff::None,
+            jitter: 0.0,
+            predicate: None,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+
+        self
+    }
+
+    // Randomly shortens each delay by up to `jitter` (0.0 - 1.0) of its length.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+
+        self
+    }
+
+    pub fn retry_if(mut self, predicate: impl Fn(&E) -> bool + Send + Sync + 'static) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    pub fn should_retry(&self, attempts: u32, error: &E) -> bool {
+        attempts < self.max_attempts && self.predicate.as_ref().is_none_or(|e| e(error))
+    }
+
+    // Delay before the attempt following `attempts` finished attempts.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential {
+                initial,
+                factor,
+                max,
+            } => {
+                let exponent = attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+                let secs = initial.as_secs_f64() * factor.powi(exponent);
+
+                // clamped before converting back, as a long run of attempts
+                // overflows what a `Duration` can hold; so can `max` itself
+                // once rounded to an `f64`
+                if secs.is_nan() {
+                    max
+                } else {
+                    Duration::try_from_secs_f64(secs.min(max.as_secs_f64())).unwrap_or(max)
+                }
+            }
+        };
+
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * random())
+        } else {
+            delay
+        }
+    }
+}
+
+// v4 UUIDs are backed by a CSPRNG already, which is plenty for jitter.
+fn random() -> f64 {
+    (Uuid::new_v4().as_u128() >> 75) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::new(3).retry_if(|e: &i32| *e > 0);
+
+        assert!(policy.should_retry(1, &1));
+        assert!(policy.should_retry(2, &1));
+        assert!(!policy.should_retry(3, &1));
+        assert!(!policy.should_retry(1, &0));
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::<()>::new(5).with_backoff(Backoff::Exponential {
+            initial: Duration::from_millis(100),
+            factor: 2.0,
+            max: Duration::from_millis(500),
+        });
+
+        assert_eq!(Duration::from_millis(100), policy.delay(1));
+        assert_eq!(Duration::from_millis(200), policy.delay(2));
+        assert_eq!(Duration::from_millis(400), policy.delay(3));
+        assert_eq!(Duration::from_millis(500), policy.delay(4));
+        assert_eq!(Duration::from_millis(500), policy.delay(80));
+        assert_eq!(Duration::from_millis(500), policy.delay(u32::MAX));
+
+        let policy = RetryPolicy::<()>::new(5).with_backoff(Backoff::Exponential {
+            initial: Duration::from_secs(1),
+            factor: 2.0,
+            max: Duration::MAX,
+        });
+
+        assert_eq!(Duration::MAX, policy.delay(u32::MAX));
+
+        let policy = RetryPolicy::<()>::new(5)
+            .with_backoff(Backoff::Fixed(Duration::from_millis(100)))
+            .with_jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+
+            assert!(delay >= Duration::from_millis(50));
+            assert!(delay <= Duration::from_millis(100));
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Schedule {
+    // runs once, at the given time
+    At(SystemTime),
+    // first runs one interval after being queued
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    // A zero interval would be due again the moment it ran, forever.
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        match self {
+            Self::Every(interval) if interval.is_zero() => Err(ScheduleError::ZeroInterval),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_recurring(&self) -> bool {
+        !matches!(self, Self::At(_))
+    }
+
+    pub fn first(&self, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::At(at) => Some(*at),
+            _ => self.next_after(now),
+        }
+    }
+
+    // The run following one that finished at `time`.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::At(_) => None,
+            Self::Every(interval) => Some(time + *interval),
+            Self::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    ZeroInterval,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroInterval => write!(f, "schedule interval must not be zero"),
+        }
+    }
+}
+
+impl Error for ScheduleError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CronError {
+    FieldCount(usize),
+    InvalidField(String),
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldCount(count) => write!(f, "expected 5 cron fields, found {}", count),
+            Self::InvalidField(field) => write!(f, "invalid cron field: {}", field),
+        }
+    }
+}
+
+impl Error for CronError {}
+
+// A standard five field cron expression (minute, hour, day of month, month,
+// day of week), evaluated in UTC. Fields accept `*`, values, ranges, steps
+// and lists; the usual `@hourly`, `@daily`, `@weekly`, `@monthly` and
+// `@yearly` shorthands work too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // with both day fields restricted, either one matching is enough
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const DAY: u64 = 24 * 60 * 60;
+// how far ahead to look for an expression like `0 0 30 2 *` that never matches
+const SEARCH_YEARS: u64 = 5;
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+
+        if fields.len() != 5 {
+            return Err(CronError::FieldCount(fields.len()));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+
+        // both 0 and 7 are Sunday
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    // The first matching minute strictly after `time`.
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let limit = secs + SEARCH_YEARS * 366 * DAY;
+
+        let mut t = (secs / 60 + 1) * 60;
+
+        while t < limit {
+            let days = t / DAY;
+            let (month, day) = month_day(days as i64);
+            // 1970-01-01 was a Thursday
+            let weekday = (days + 4) % 7;
+
+            if !self.matches_day(month, day, weekday) {
+                t = (days + 1) * DAY;
+                continue;
+            }
+
+            let hour = t % DAY / 3600;
+
+            if self.hours & 1 << hour == 0 {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+
+            if self.minutes & 1 << (t % 3600 / 60) == 0 {
+                t += 60;
+                continue;
+            }
+
+            return Some(UNIX_EPOCH + Duration::from_secs(t));
+        }
+
+        None
+    }
+
+    fn matches_day(&self, month: u64, day: u64, weekday: u64) -> bool {
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+
+        let day = self.days & 1 << day != 0;
+        let weekday = self.weekdays & 1 << weekday != 0;
+
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = CronError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<Cron> for String {
+    fn from(value: Cron) -> Self {
+        value.expression
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.expression)
+    }
+}
+
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, CronError> {
+    let invalid = || CronError::InvalidField(field.to_string());
+    let number = |e: &str| e.parse::<u64>().map_err(|_| invalid());
+
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(number(step)?)),
+            None => (part, None),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+
+        if step == Some(0) || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+// Month and day of month for a count of days since 1970-01-01, after
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn month_day(days: i64) -> (u64, u64) {
+    let z = days + 719_468;
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (month as u64, day as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Err(CronError::FieldCount(3)), Cron::parse("* * *"));
+        assert_eq!(
+            Err(CronError::InvalidField("61".to_string())),
+            Cron::parse("61 * * * *")
+        );
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("5-1 * * * *").is_err());
+        assert!(Cron::parse("x * * * *").is_err());
+
+        let cron = Cron::parse("*/15 9-17 * * 1-5").unwrap();
+
+        assert_eq!(1 | 1 << 15 | 1 << 30 | 1 << 45, cron.minutes);
+        assert_eq!("*/15 9-17 * * 1-5", cron.to_string());
+        assert_eq!(cron, "*/15 9-17 * * 1-5".parse().unwrap());
+
+        let json = serde_json::to_string(&Schedule::Cron(cron.clone())).unwrap();
+
+        assert_eq!(r#"{"cron":"*/15 9-17 * * 1-5"}"#, json);
+        assert_eq!(Schedule::Cron(cron), serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // Friday 2024-03-01 10:07:00 UTC
+        let now = at(1_709_287_620);
+        let next = |e: &str| Cron::parse(e).unwrap().next_after(now);
+
+        assert_eq!(Some(at(1_709_288_100)), next("*/15 * * * *"));
+        assert_eq!(Some(at(1_709_542_800)), next("0 9 * * 1"));
+        // the 4th or any Sunday, and the 3rd is a Sunday
+        assert_eq!(Some(at(1_709_456_400)), next("0 9 4 * 7"));
+        assert_eq!(Some(at(1_709_337_600)), next("@daily"));
+        assert_eq!(Some(at(1_711_929_600)), next("@monthly"));
+        assert_eq!(Some(at(1_835_395_200)), next("0 0 29 2 *"));
+        assert_eq!(None, next("0 0 30 2 *"));
+
+        let every = Schedule::Every(Duration::from_secs(60));
+
+        assert!(every.is_recurring());
+        assert_eq!(Some(now + Duration::from_secs(60)), every.first(now));
+        assert_eq!(Some(now), Schedule::At(now).first(now));
+        assert_eq!(None, Schedule::At(now).next_after(now));
+        assert_eq!(
+            Err(ScheduleError::ZeroInterval),
+            Schedule::Every(Duration::ZERO).validate()
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::{CallOutcome, PushError, Shared, Task, TaskManager};
+
+// A `TaskManager` behind a lock, for several threads to push to and pull from.
+pub struct SharedTaskManager<T, U, R = (), E = ()> {
+    manager: Mutex<TaskManager<T, U, R, E, Shared>>,
+    changed: Condvar,
+    closed: AtomicBool,
+}
+
+impl<T, U, R, E> SharedTaskManager<T, U, R, E> {
+    pub fn new(manager: TaskManager<T, U, R, E, Shared>) -> Self {
+        Self {
+            manager: Mutex::new(manager),
+            changed: Condvar::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn into_inner(self) -> TaskManager<T, U, R, E, Shared> {
+        self.manager.into_inner().unwrap()
+    }
+
+    // Waiting threads are woken afterwards, as `f` may have made tasks ready.
+    pub fn with<X>(&self, f: impl FnOnce(&mut TaskManager<T, U, R, E, Shared>) -> X) -> X {
+        let result = f(&mut self.lock());
+
+        self.changed.notify_all();
+
+        result
+    }
+
+    pub fn push(&self, task: Task<T, U, R, E, Shared>) {
+        self.with(|e| e.push(task));
+    }
+
+    pub fn try_push(&self, task: Task<T, U, R, E, Shared>) -> Result<Uuid, PushError> {
+        self.with(|e| e.try_push(task))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn cancel(&self, uuid: Uuid) -> bool {
+        self.with(|e| e.cancel(uuid))
+    }
+
+    pub fn cancel_all(&self) -> usize {
+        self.with(|e| e.cancel_all())
+    }
+
+    pub fn pause(&self) {
+        self.with(|e| e.pause());
+    }
+
+    // Wakes the threads waiting for a task, as the queued ones are poppable
+    // again.
+    pub fn resume(&self) {
+        self.with(|e| e.resume());
+    }
+
+    // Wakes every blocked `pop`; from then on `pop` returns `None` instead of
+    // waiting for more tasks.
+    pub fn close(&self) {
+        // under the lock, or a `pop` between its check and its wait would
+        // miss the wakeup
+        let _manager = self.lock();
+
+        self.closed.store(true, Ordering::SeqCst);
+        self.changed.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // Like `TaskManager::drain`, with the threads of `pool` calling the tasks.
+    pub fn drain(&self, pool: &WorkerPool, data: &U) -> Vec<CallOutcome<T, U, R, E, Shared>>
+    where
+        T: Send,
+        U: Sync,
+        R: Send,
+        E: Send,
+    {
+        self.with(|e| e.start_drain());
+
+        pool.run(self, data)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.lock().is_draining()
+    }
+
+    pub fn end_drain(&self) {
+        self.with(|e| e.end_drain());
+    }
+
+    pub fn try_pop(&self) -> Option<Task<T, U, R, E, Shared>> {
+        self.lock().acquire()
+    }
+
+    // Blocks until a task is ready or the manager is closed.
+    pub fn pop(&self) -> Option<Task<T, U, R, E, Shared>> {
+        self.pop_until(None)
+    }
+
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<Task<T, U, R, E, Shared>> {
+        self.pop_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn finish(
+        &self,
+        task: Task<T, U, R, E, Shared>,
+        result: Result<R, E>,
+    ) -> CallOutcome<T, U, R, E, Shared> {
+        self.with(|e| e.finish(task, result))
+    }
+
+    pub fn release(&self, uuid: Uuid) -> bool {
+        self.with(|e| e.release(uuid))
+    }
+
+    fn pop_until(&self, until: Option<Instant>) -> Option<Task<T, U, R, E, Shared>> {
+        let mut manager = self.lock();
+
+        loop {
+            if let Some(task) = manager.acquire() {
+                return Some(task);
+            }
+
+            if self.is_closed() {
+                return None;
+            }
+
+            let mut wait = next_wake(&manager);
+
+            if let Some(until) = until {
+                let left = until.saturating_duration_since(Instant::now());
+
+                if left.is_zero() {
+                    return None;
+                }
+
+                wait = Some(wait.map_or(left, |e| e.min(left)));
+            }
+
+            manager = self.wait(manager, wait);
+        }
+    }
+
+    // Runs the next task with the manager unlocked, so other workers can run
+    // theirs meanwhile. Returns `None` once no task is being called and no
+    // queued one is ready or due, such as when only suspended tasks and their
+    // dependents are left; tasks taken with `pop` are not waited for, and a
+    // paused queue is waited on until it is resumed.
+    fn call_next(&self, data: &U) -> Option<CallOutcome<T, U, R, E, Shared>> {
+        let mut manager = self.lock();
+
+        let (mut task, invocation) = loop {
+            if let Some(started) = manager.start_call() {
+                break started;
+            }
+
+            let wait = next_wake(&manager);
+
+            if manager.running.values().all(|e| e.popped)
+                && wait.is_none()
+                && (manager.is_empty() || !manager.is_paused())
+            {
+                return None;
+            }
+
+            manager = self.wait(manager, wait);
+        };
+
+        let middleware = manager.middleware.clone();
+
+        drop(manager);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            middleware.run(&invocation, || task.call_ref(data))
+        }));
+
+        match result {
+            Ok(result) => Some(self.with(|e| e.finish_call(task, result))),
+            Err(e) => {
+                // don't leave the other workers waiting on a task that is gone
+                self.with(|manager| manager.abandon_call(task));
+                panic::resume_unwind(e)
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TaskManager<T, U, R, E, Shared>> {
+        self.manager.lock().unwrap()
+    }
+
+    fn wait<'a>(
+        &self,
+        manager: MutexGuard<'a, TaskManager<T, U, R, E, Shared>>,
+        timeout: Option<Duration>,
+    ) -> MutexGuard<'a, TaskManager<T, U, R, E, Shared>> {
+        match timeout {
+            Some(timeout) => self.changed.wait_timeout(manager, timeout).unwrap().0,
+            None => self.changed.wait(manager).unwrap(),
+        }
+    }
+}
+
+fn next_wake<T, U, R, E>(manager: &TaskManager<T, U, R, E, Shared>) -> Option<Duration> {
+    let now = manager.clock.now();
+
+    manager
+        .next_wake()
+        .map(|e| e.duration_since(now).unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerPool {
+    threads: usize,
+}
+
+impl WorkerPool {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    // Drains `manager` and returns once nothing is left that could run, with
+    // the outcomes in the order the calls finished.
+    pub fn run<T, U, R, E>(
+        &self,
+        manager: &SharedTaskManager<T, U, R, E>,
+        data: &U,
+    ) -> Vec<CallOutcome<T, U, R, E, Shared>>
+    where
+        T: Send,
+        U: Sync,
+        R: Send,
+        E: Send,
+    {
+        let outcomes = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| {
+                    while let Some(outcome) = manager.call_next(data) {
+                        outcomes.lock().unwrap().push(outcome);
+                    }
+                });
+            }
+        });
+
+        outcomes.into_inner().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventKind, RetryPolicy};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    fn assert_send_sync<X: Send + Sync>() {}
+
+    #[test]
+    fn test_pop() {
+        assert_send_sync::<SharedTaskManager<i32, i32, i32, String>>();
+
+        let shared = SharedTaskManager::new(TaskManager::<i32, (), (), (), _>::new_in(Shared));
+
+        assert!(shared.pop_timeout(Duration::from_millis(10)).is_none());
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                shared.push(Task::new_in(1, Shared));
+            });
+
+            assert_eq!(&1, shared.pop().unwrap().data());
+        });
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                shared.close();
+            });
+
+            assert!(shared.pop().is_none());
+        });
+
+        shared.push(Task::new_in(2, Shared));
+
+        assert_eq!(&2, shared.try_pop().unwrap().data());
+        assert!(shared.is_empty());
+    }
+
+    #[test]
+    fn test_worker_pool() {
+        let mut manager = TaskManager::<usize, AtomicUsize, usize, usize, _>::new_in(Shared);
+        let mut uuids = Vec::new();
+
+        manager.set_retry_policy(Some(RetryPolicy::new(2)));
+
+        for i in 0..20 {
+            let mut task = Task::new_in(i, Shared);
+
+            task.set_callback(|this, count: &AtomicUsize| {
+                // every third task fails on its first attempt
+                if this.data() % 3 == 0 && this.called() == 1 {
+                    return Err(*this.data());
+                }
+
+                thread::sleep(Duration::from_millis(1));
+
+                Ok(count.fetch_add(1, Ordering::SeqCst))
+            });
+
+            uuids.push(task.uuid());
+            manager.push(task);
+        }
+
+        // the last task only runs once every other one succeeded
+        for i in 0..19 {
+            manager.add_dependency(uuids[19], uuids[i]).unwrap();
+        }
+
+        let shared = SharedTaskManager::new(manager);
+        let count = AtomicUsize::new(0);
+        let outcomes = WorkerPool::new(4).run(&shared, &count);
+
+        assert_eq!(20, count.load(Ordering::SeqCst));
+        assert_eq!(27, outcomes.len());
+        assert_eq!(7, outcomes.iter().filter(|e| e.result.is_err()).count());
+
+        let last = outcomes.last().unwrap();
+
+        assert_eq!(uuids[19], last.uuid);
+        assert_eq!(Ok(19), last.result);
+
+        let manager = shared.into_inner();
+
+        assert!(manager.is_empty());
+        assert!(manager.dead_letters().is_empty());
+    }
+
+    #[test]
+    fn test_drain() {
+        let shared = SharedTaskManager::new(TaskManager::<i32, (), i32, (), _>::new_in(Shared));
+
+        for i in 0..4 {
+            let mut task = Task::new_in(i, Shared);
+
+            task.set_callback(|this, _| Ok(*this.data()));
+            shared.push(task);
+        }
+
+        shared.pause();
+
+        let outcomes = shared.drain(&WorkerPool::new(2), &());
+
+        assert_eq!(4, outcomes.len());
+        assert!(shared.is_empty());
+        assert!(shared.is_draining());
+        assert_eq!(
+            Err(PushError::Draining),
+            shared.try_push(Task::new_in(4, Shared))
+        );
+
+        shared.end_drain();
+
+        assert!(shared.try_push(Task::new_in(5, Shared)).is_ok());
+
+        // left in the queue rather than waited on
+        let suspended = Task::new_in(6, Shared);
+        let uuid = suspended.uuid();
+
+        shared.push(suspended);
+        shared.with(|e| e.suspend(uuid));
+
+        assert_eq!(1, shared.drain(&WorkerPool::new(2), &()).len());
+        assert_eq!(1, shared.len());
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut manager = TaskManager::<i32, (), (), (), _>::new_in(Shared);
+        let (sender, events) = mpsc::channel();
+
+        manager.subscribe(sender);
+
+        for i in 0..4 {
+            let mut task = Task::new_in(i, Shared);
+
+            task.set_callback(|this, _| {
+                if *this.data() == 2 {
+                    panic!("task 2");
+                }
+
+                Ok(())
+            });
+
+            manager.push(task);
+        }
+
+        let panicking = manager.iter().nth(2).unwrap().uuid();
+        let shared = SharedTaskManager::new(manager);
+        let result = panic::catch_unwind(|| WorkerPool::new(2).run(&shared, &()));
+
+        assert!(result.is_err());
+
+        // the other workers carry on, and the task that panicked is kept
+        let manager = shared.into_inner();
+
+        assert!(manager.is_empty());
+        assert!(!manager.is_running(panicking));
+        assert_eq!(1, manager.dead_letters().len());
+        assert_eq!(panicking, manager.dead_letters()[0].uuid());
+        assert!(events
+            .try_iter()
+            .any(|e| e.uuid == panicking && e.kind == EventKind::Failed));
+    }
+}
@@ -1,17 +1,275 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 
 use uuid::Uuid;
 
-trait Task<T, U, R, E> {
+use crate::clock::{Clock, SystemClock};
+use crate::event::Observers;
+use crate::executor::{self, BlockingExecutor, BoxFuture, Executor};
+use crate::journal::{Journal, JournalEntry, JournalError, Phase, Recovery};
+use crate::middleware::{Chain, Invocation, Middleware, Operation};
+use crate::{CancellationToken, Event, EventKind, Interrupt, Observer, RetryPolicy};
+
+// `uuid` must return the same value on every call: the invoker finds its
+// place in the history by it. Tasks without an identity of their own can be
+// wrapped in `Identified`.
+pub trait Command<T, U, R, E> {
     fn uuid(&self) -> Uuid;
     fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E>;
     fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E>;
+
+    // Takes `&mut` so that a task can look at `data` through state of its
+    // own, as closure tasks do; it must leave both as it found them.
+    fn dry_run(&mut self, _data: &mut T, _arg: &U) -> bool {
+        true
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    // Tasks that can stop early should override this and poll `token`.
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        _token: &CancellationToken,
+    ) -> Result<R, E> {
+        self.execute(data, arg)
+    }
+
+    // What `Invoker::execute_async` awaits; by default the task runs just as
+    // it does from `execute`.
+    fn execute_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+        token: &'f CancellationToken,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        Box::pin(async move { self.execute_with_token(data, arg, token) })
+    }
+
+    fn rollback_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        Box::pin(async move { self.rollback(data, arg) })
+    }
+}
+
+type ExecuteFn<'a, T, U, R, E> = Box<dyn FnMut(&mut T, &mut U) -> Result<R, E> + 'a>;
+type DryRunFn<'a, T, U> = Box<dyn Fn(&T, &U) -> bool + 'a>;
+
+pub struct FnCommand<'a, T, U, R, E> {
+    uuid: Uuid,
+    execute: ExecuteFn<'a, T, U, R, E>,
+    rollback: Option<ExecuteFn<'a, T, U, R, E>>,
+    dry_run: Option<DryRunFn<'a, T, U>>,
+    timeout: Option<Duration>,
+}
+
+impl<'a, T, U, R, E> FnCommand<'a, T, U, R, E> {
+    pub fn new(execute: impl FnMut(&mut T, &mut U) -> Result<R, E> + 'a) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            execute: Box::new(execute),
+            rollback: None,
+            dry_run: None,
+            timeout: None,
+        }
+    }
+
+    pub fn with_rollback(
+        mut self,
+        rollback: impl FnMut(&mut T, &mut U) -> Result<R, E> + 'a,
+    ) -> Self {
+        self.rollback = Some(Box::new(rollback));
+
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: impl Fn(&T, &U) -> bool + 'a) -> Self {
+        self.dry_run = Some(Box::new(dry_run));
+
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+}
+
+impl<T, U, R, E> Command<T, U, R, E> for FnCommand<'_, T, U, R, E>
+where
+    R: Default,
+{
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        (self.execute)(data, arg)
+    }
+
+    // without a rollback closure there is nothing to undo
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        match self.rollback.as_mut() {
+            Some(rollback) => rollback(data, arg),
+            None => Ok(R::default()),
+        }
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        self.dry_run.as_ref().is_none_or(|e| e(data, arg))
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+// Gives a task a UUID of its own, assigned once when it is wrapped.
+pub struct Identified<X> {
+    uuid: Uuid,
+    task: X,
+}
+
+impl<X> Identified<X> {
+    pub fn new(task: X) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            task,
+        }
+    }
+
+    pub fn get_ref(&self) -> &X {
+        &self.task
+    }
+
+    pub fn get_mut(&mut self) -> &mut X {
+        &mut self.task
+    }
+
+    pub fn into_inner(self) -> X {
+        self.task
+    }
+}
+
+impl<X, T, U, R, E> Command<T, U, R, E> for Identified<X>
+where
+    X: Command<T, U, R, E>,
+{
+    fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn execute(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.task.execute(data, arg)
+    }
+
+    fn rollback(&mut self, data: &mut T, arg: &mut U) -> Result<R, E> {
+        self.task.rollback(data, arg)
+    }
+
+    fn dry_run(&mut self, data: &mut T, arg: &U) -> bool {
+        self.task.dry_run(data, arg)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.task.timeout()
+    }
+
+    fn execute_with_token(
+        &mut self,
+        data: &mut T,
+        arg: &mut U,
+        token: &CancellationToken,
+    ) -> Result<R, E> {
+        self.task.execute_with_token(data, arg, token)
+    }
+
+    fn execute_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+        token: &'f CancellationToken,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        self.task.execute_async(data, arg, token)
+    }
+
+    fn rollback_async<'f>(
+        &'f mut self,
+        data: &'f mut T,
+        arg: &'f mut U,
+    ) -> BoxFuture<'f, Result<R, E>>
+    where
+        R: 'f,
+        E: 'f,
+    {
+        self.task.rollback_async(data, arg)
+    }
+}
+
+pub struct TransactionReport<R, E> {
+    pub executed: Vec<(Uuid, R)>,
+    pub failed: Option<(Uuid, E)>,
+    pub rolled_back: Vec<(Uuid, Result<R, E>)>,
+    pub interrupted: Option<(Uuid, Interrupt)>,
+}
+
+impl<R, E> TransactionReport<R, E> {
+    pub fn is_committed(&self) -> bool {
+        self.failed.is_none() && self.interrupted.is_none()
+    }
+
+    pub fn rollback_failures(&self) -> impl Iterator<Item = (&Uuid, &E)> {
+        self.rolled_back
+            .iter()
+            .filter_map(|(uuid, result)| result.as_ref().err().map(|e| (uuid, e)))
+    }
+}
+
+type JournalErrorFn<E> = fn(JournalError) -> E;
+
+// Whether the invoker blocks on its tasks or awaits them. Blocking, nothing
+// it awaits is ever pending, so `block_on` returns on the first poll.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Blocking,
+    Async,
 }
 
 pub struct Invoker<'a, T: 'a, U: 'a, R: 'a, E: 'a> {
-    tasks: VecDeque<Box<dyn Task<T, U, R, E> + 'a>>,
+    tasks: VecDeque<Box<dyn Command<T, U, R, E> + 'a>>,
     data: &'a mut T,
     current_uuid: Option<Uuid>,
+    redo_len: usize,
+    history_depth: Option<usize>,
+    retry_policy: Option<RetryPolicy<E>>,
+    // how many attempts the last execute of each task took
+    attempts: HashMap<Uuid, u32>,
+    middleware: Chain<'a, Result<R, E>>,
+    observers: Observers<'a>,
+    journal: Option<(Journal, JournalErrorFn<E>)>,
+    clock: Arc<dyn Clock>,
+    token: CancellationToken,
+    last_interrupt: Option<(Uuid, Interrupt)>,
 }
 
 impl<'a, T, U, R, E> Invoker<'a, T, U, R, E>
@@ -23,11 +281,21 @@ where
             tasks: VecDeque::new(),
             data,
             current_uuid: None,
+            redo_len: 0,
+            history_depth: None,
+            retry_policy: None,
+            attempts: HashMap::new(),
+            middleware: Chain::new(),
+            observers: Observers::default(),
+            journal: None,
+            clock: Arc::new(SystemClock),
+            token: CancellationToken::new(),
+            last_interrupt: None,
         }
     }
 
     pub fn data(&self) -> &T {
-        &self.data
+        self.data
     }
 
     pub fn set_data(&mut self, data: &'a mut T) -> &mut Self {
@@ -36,84 +304,546 @@ where
         self
     }
 
+    pub fn history_depth(&self) -> Option<usize> {
+        self.history_depth
+    }
+
+    pub fn set_history_depth(&mut self, depth: Option<usize>) -> &mut Self {
+        self.history_depth = depth;
+        self.trim_history();
+
+        self
+    }
+
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy<E>>) -> &mut Self {
+        self.retry_policy = policy;
+
+        self
+    }
+
+    pub fn add_middleware(&mut self, middleware: impl Middleware<Result<R, E>> + 'a) -> &mut Self {
+        self.middleware.push(middleware);
+
+        self
+    }
+
+    pub fn subscribe(&mut self, observer: impl Observer + 'a) -> &mut Self {
+        self.observers.push(observer);
+
+        self
+    }
+
+    // With a journal, every execute and rollback is recorded before it runs,
+    // and a failure to record it fails the operation instead.
+    pub fn set_journal(&mut self, journal: Option<Journal>) -> &mut Self
+    where
+        E: From<JournalError>,
+    {
+        self.journal = journal.map(|e| (e, E::from as JournalErrorFn<E>));
+
+        self
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref().map(|e| &e.0)
+    }
+
+    // Rolls back the tasks a crash left half done, according to the journal.
+    // Tasks are matched by UUID, so they must be pushed again first.
+    pub fn recover(&mut self, arg: &mut U) -> Result<Recovery<R, E>, JournalError> {
+        let (in_doubt, rollbacks_in_doubt) = match &self.journal {
+            Some((journal, _)) => (journal.in_doubt()?, journal.rollbacks_in_doubt()?),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let mut recovery = Recovery {
+            rolled_back: Vec::new(),
+            missing: Vec::new(),
+            rollbacks_in_doubt,
+        };
+
+        for uuid in in_doubt {
+            match self.tasks.iter().position(|e| e.uuid() == uuid) {
+                Some(index) => {
+                    let result = block_on(self.rollback_at(Mode::Blocking, index, arg));
+                    recovery.rolled_back.push((uuid, result));
+                }
+                None => recovery.missing.push(uuid),
+            }
+        }
+
+        Ok(recovery)
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = clock;
+
+        self
+    }
+
+    // Cancelling this token interrupts the running task and every later one,
+    // until a fresh token is set.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn set_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.token = token;
+
+        self
+    }
+
+    pub fn last_interrupt(&self) -> Option<(Uuid, Interrupt)> {
+        self.last_interrupt
+    }
+
     pub fn clear(&mut self) -> &mut Self {
         self.tasks.clear();
+        self.attempts.clear();
         self.current_uuid = None;
+        self.redo_len = 0;
 
         self
     }
 
-    pub fn push<X: Task<T, U, R, E> + 'a>(&mut self, task: X) -> &mut Self {
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // Pushing after an undo drops the undone tasks, so they can no longer be
+    // redone, but tasks pushed earlier and not executed yet stay queued ahead
+    // of the new one.
+    pub fn push<X: Command<T, U, R, E> + 'a>(&mut self, task: X) -> &mut Self {
+        debug_assert!(
+            task.uuid() == task.uuid(),
+            "Command::uuid returned a different value on each call; \
+             push the task with push_identified instead"
+        );
+
+        if self.redo_len > 0 {
+            let cursor = self.cursor();
+
+            for task in self.tasks.drain(cursor..cursor + self.redo_len) {
+                self.attempts.remove(&task.uuid());
+            }
+
+            self.redo_len = 0;
+        }
+
+        self.emit(task.uuid(), EventKind::Queued, 0);
         self.tasks.push_back(Box::new(task));
 
         self
     }
 
-    pub fn pop(&mut self) -> Option<Box<dyn Task<T, U, R, E> + 'a>> {
-        self.tasks.pop_front()
+    // For tasks whose `uuid` is not stable; returns the UUID they were given.
+    pub fn push_identified<X: Command<T, U, R, E> + 'a>(&mut self, task: X) -> Uuid {
+        let task = Identified::new(task);
+        let uuid = task.uuid;
+
+        self.push(task);
+
+        uuid
     }
 
-    pub fn index(&self) -> usize {
-        match self.current_uuid {
-            Some(uuid) => {
-                for (i, ele) in self.tasks.iter().enumerate() {
-                    if ele.uuid() == uuid {
-                        return i;
-                    }
-                }
-                return 0;
-            }
-            None => 0,
+    pub fn pop(&mut self) -> Option<Box<dyn Command<T, U, R, E> + 'a>> {
+        if self.cursor() == 0 {
+            self.redo_len = self.redo_len.saturating_sub(1);
         }
+
+        let result = self.tasks.pop_front()?;
+
+        self.attempts.remove(&result.uuid());
+
+        if self.current_uuid == Some(result.uuid()) {
+            self.current_uuid = None;
+        }
+
+        Some(result)
+    }
+
+    pub fn index(&self) -> usize {
+        self.current().unwrap_or(0)
+    }
+
+    pub fn dry_run(&mut self, arg: &U) -> bool {
+        let cursor = self.cursor();
+
+        self.tasks
+            .iter_mut()
+            .skip(cursor)
+            .all(|e| e.dry_run(self.data, arg))
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.cursor() > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.redo_len > 0
     }
 
+    // A task that finishes despite being cancelled or timing out is rolled
+    // back, as it may only have been partially applied, and the rollback's
+    // result returned, with the interrupt left in `last_interrupt`; one that
+    // gave up with an error is left as it is, like any other failed task.
     pub fn execute(&mut self, arg: &mut U) -> Result<R, E> {
-        if self.tasks.len() <= 0 {
+        block_on(self.execute_in(Mode::Blocking, arg))
+    }
+
+    pub async fn execute_async(&mut self, arg: &mut U) -> Result<R, E> {
+        self.execute_in(Mode::Async, arg).await
+    }
+
+    async fn execute_in(&mut self, mode: Mode, arg: &mut U) -> Result<R, E> {
+        let cursor = self.cursor();
+
+        if cursor >= self.tasks.len() {
             // NOP
             return Ok(R::default());
         }
 
-        let current_index = self.index();
-        let c = self.tasks.get_mut(current_index).unwrap();
-        // let t = &mut *self.data;
+        let uuid = self.tasks[cursor].uuid();
+        let result = self.execute_at(mode, cursor, arg).await?;
 
-        let result = c.execute(&mut self.data, arg);
+        if self.last_interrupt.is_some() {
+            self.record(uuid, Operation::Execute, Phase::Completed)?;
 
-        if result.is_err() {
-            return result;
+            return self.rollback_at(mode, cursor, arg).await;
         }
 
-        self.current_uuid = Some(c.uuid());
+        self.current_uuid = Some(uuid);
+        self.redo_len = self.redo_len.saturating_sub(1);
 
-        result
+        // recorded once the cursor moved on, so that failing to record it
+        // does not leave the task to be executed again
+        let recorded = self.record(uuid, Operation::Execute, Phase::Completed);
+
+        self.trim_history();
+        recorded?;
+
+        Ok(result)
     }
 
+    #[deprecated(note = "use `undo`")]
     pub fn rollback(&mut self, arg: &mut U) -> Result<R, E> {
-        if self.tasks.len() <= 0 {
+        self.undo(arg)
+    }
+
+    pub fn undo(&mut self, arg: &mut U) -> Result<R, E> {
+        block_on(self.undo_in(Mode::Blocking, arg))
+    }
+
+    pub async fn undo_async(&mut self, arg: &mut U) -> Result<R, E> {
+        self.undo_in(Mode::Async, arg).await
+    }
+
+    async fn undo_in(&mut self, mode: Mode, arg: &mut U) -> Result<R, E> {
+        let cursor = self.cursor();
+
+        if cursor == 0 {
             // NOP
             return Ok(R::default());
         }
 
-        let current_index = self.index();
-        let c = self.tasks.get_mut(current_index).unwrap();
-        // let t = &mut *self.data;
+        let result = self.rollback_at(mode, cursor - 1, arg).await?;
+
+        self.current_uuid = if cursor > 1 {
+            Some(self.tasks[cursor - 2].uuid())
+        } else {
+            None
+        };
+        self.redo_len += 1;
+
+        Ok(result)
+    }
 
-        let result = c.rollback(&mut self.data, arg);
+    pub fn redo(&mut self, arg: &mut U) -> Result<R, E> {
+        block_on(self.redo_in(Mode::Blocking, arg))
+    }
+
+    pub async fn redo_async(&mut self, arg: &mut U) -> Result<R, E> {
+        self.redo_in(Mode::Async, arg).await
+    }
 
+    async fn redo_in(&mut self, mode: Mode, arg: &mut U) -> Result<R, E> {
+        if !self.can_redo() {
+            // NOP
+            return Ok(R::default());
+        }
+
+        self.execute_in(mode, arg).await
+    }
+
+    pub fn run_all(&mut self, arg: &mut U) -> TransactionReport<R, E> {
+        block_on(self.run_all_in(Mode::Blocking, arg))
+    }
+
+    pub async fn run_all_async(&mut self, arg: &mut U) -> TransactionReport<R, E> {
+        self.run_all_in(Mode::Async, arg).await
+    }
+
+    async fn run_all_in(&mut self, mode: Mode, arg: &mut U) -> TransactionReport<R, E> {
+        let mut report = TransactionReport {
+            executed: Vec::new(),
+            failed: None,
+            rolled_back: Vec::new(),
+            interrupted: None,
+        };
+
+        let previous_uuid = self.current_uuid;
+        let cursor = self.cursor();
+
+        for i in cursor..self.tasks.len() {
+            let uuid = self.tasks[i].uuid();
+
+            // what went wrong, if anything, and whether the task was applied
+            let (failed, applied) = match self.execute_at(mode, i, arg).await {
+                Ok(result) => {
+                    let recorded = self.record(uuid, Operation::Execute, Phase::Completed);
+
+                    if recorded.is_ok() && self.last_interrupt.is_none() {
+                        self.current_uuid = Some(uuid);
+                        report.executed.push((uuid, result));
+                        continue;
+                    }
+
+                    (recorded.err(), true)
+                }
+                // a failed task is never rolled back, interrupted or not
+                Err(e) => (Some(e), false),
+            };
+
+            report.failed = failed.map(|e| (uuid, e));
+            report.interrupted = self.last_interrupt;
+
+            let end = if applied { i + 1 } else { i };
+
+            for j in (cursor..end).rev() {
+                let uuid = self.tasks[j].uuid();
+                let result = self.rollback_at(mode, j, arg).await;
+
+                report.rolled_back.push((uuid, result));
+            }
+
+            self.current_uuid = previous_uuid;
+
+            return report;
+        }
+
+        self.redo_len = 0;
+        self.trim_history();
+
+        report
+    }
+
+    // The timeout covers every attempt, including the delays between them.
+    async fn execute_at(&mut self, mode: Mode, index: usize, arg: &mut U) -> Result<R, E> {
+        let token = self.token.child();
+        let uuid = self.tasks[index].uuid();
+        let mut attempts = 0;
+
+        self.last_interrupt = None;
+        self.record(uuid, Operation::Execute, Phase::Started)?;
+
+        token.set_timeout(&self.clock, self.tasks[index].timeout());
+
+        let result = loop {
+            attempts += 1;
+
+            let invocation = Invocation {
+                uuid,
+                operation: Operation::Execute,
+                attempt: attempts,
+            };
+
+            self.emit(uuid, EventKind::Started, attempts);
+
+            let c = self.tasks.get_mut(index).unwrap();
+
+            let result = match mode {
+                Mode::Blocking => self
+                    .middleware
+                    .run(&invocation, || c.execute_with_token(self.data, arg, &token)),
+                Mode::Async => {
+                    self.middleware
+                        .run_async(&invocation, c.execute_async(self.data, arg, &token))
+                        .await
+                }
+            };
+
+            match result {
+                Err(e) => match &self.retry_policy {
+                    Some(policy) if !token.is_cancelled() && policy.should_retry(attempts, &e) => {
+                        self.emit(uuid, EventKind::Retried, attempts);
+                        self.sleep(mode, policy.delay(attempts)).await;
+
+                        if token.is_cancelled() {
+                            break Err(e);
+                        }
+                    }
+                    _ => break Err(e),
+                },
+                result => break result,
+            }
+        };
+
+        self.last_interrupt = token.interrupt().map(|e| (uuid, e));
+        self.attempts.insert(uuid, attempts);
+
+        let kind = match result {
+            Ok(_) => EventKind::Succeeded,
+            Err(_) => EventKind::Failed,
+        };
+
+        self.emit(uuid, kind, attempts);
+
+        if token.interrupt() == Some(Interrupt::Cancelled) {
+            self.emit(uuid, EventKind::Cancelled, attempts);
+        }
+
+        // a successful execute is recorded by the caller once it has moved
+        // the cursor on; left unrecorded, the task stays in doubt and recovery
+        // rolls it back
         if result.is_err() {
-            return result;
+            self.record(uuid, Operation::Execute, Phase::Failed)?;
         }
 
-        self.current_uuid = Some(c.uuid());
+        result
+    }
+
+    async fn rollback_at(&mut self, mode: Mode, index: usize, arg: &mut U) -> Result<R, E> {
+        let uuid = self.tasks[index].uuid();
+
+        self.record(uuid, Operation::Rollback, Phase::Started)?;
+
+        let c = self.tasks.get_mut(index).unwrap();
+
+        let invocation = Invocation {
+            uuid,
+            operation: Operation::Rollback,
+            attempt: 1,
+        };
+
+        let result = match mode {
+            Mode::Blocking => self
+                .middleware
+                .run(&invocation, || c.rollback(self.data, arg)),
+            Mode::Async => {
+                self.middleware
+                    .run_async(&invocation, c.rollback_async(self.data, arg))
+                    .await
+            }
+        };
+
+        self.emit_rollback(uuid, &result);
+        self.record(uuid, Operation::Rollback, phase(&result))?;
 
         result
     }
+
+    async fn sleep(&self, mode: Mode, duration: Duration) {
+        match mode {
+            Mode::Blocking => self.clock.sleep(duration),
+            Mode::Async => executor::sleep(self.clock.clone(), duration).await,
+        }
+    }
+
+    fn record(&mut self, uuid: Uuid, operation: Operation, phase: Phase) -> Result<(), E> {
+        let timestamp = self.clock.now();
+
+        match &mut self.journal {
+            Some((journal, error)) => journal
+                .append(&JournalEntry {
+                    uuid,
+                    operation,
+                    phase,
+                    timestamp,
+                })
+                .map_err(*error),
+            None => Ok(()),
+        }
+    }
+
+    fn emit(&self, uuid: Uuid, kind: EventKind, attempt: u32) {
+        if !self.observers.is_empty() {
+            self.observers.emit(Event {
+                uuid,
+                kind,
+                timestamp: self.clock.now(),
+                attempt,
+            });
+        }
+    }
+
+    // with the attempts the execute being rolled back took, or none when it
+    // ran before a crash
+    fn emit_rollback(&self, uuid: Uuid, result: &Result<R, E>) {
+        let kind = match result {
+            Ok(_) => EventKind::RolledBack,
+            Err(_) => EventKind::RollbackFailed,
+        };
+
+        self.emit(uuid, kind, self.attempts.get(&uuid).copied().unwrap_or(0));
+    }
+
+    // number of executed tasks, i.e. the position of the next task to execute
+    fn cursor(&self) -> usize {
+        self.current().map_or(0, |i| i + 1)
+    }
+
+    // position of the last executed task
+    fn current(&self) -> Option<usize> {
+        let uuid = self.current_uuid?;
+        let position = self.tasks.iter().position(|e| e.uuid() == uuid);
+
+        // otherwise the history would silently start over from the first task
+        debug_assert!(
+            position.is_some(),
+            "the last executed task {} is gone; does its Command::uuid change between calls?",
+            uuid
+        );
+
+        position
+    }
+
+    fn trim_history(&mut self) {
+        if let Some(depth) = self.history_depth {
+            let cursor = self.cursor();
+            let excess = cursor.saturating_sub(depth);
+
+            for task in self.tasks.drain(..excess) {
+                self.attempts.remove(&task.uuid());
+            }
+
+            if excess == cursor {
+                self.current_uuid = None;
+            }
+        }
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    BlockingExecutor.block_on(future)
+}
+
+fn phase<R, E>(result: &Result<R, E>) -> Phase {
+    match result {
+        Ok(_) => Phase::Completed,
+        Err(_) => Phase::Failed,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::clock::ManualClock;
+    use crate::Backoff;
+    use std::time::{Duration, SystemTime};
 
     struct Target {
         val: i32,
@@ -127,17 +857,11 @@ mod tests {
         fn get(&self) -> i32 {
             self.val
         }
-
-        fn set(&mut self, new_val: i32) -> &mut Self {
-            self.val = new_val;
-
-            self
-        }
     }
 
     struct UpdateOneTask;
 
-    impl Task<Target, i32, bool, ()> for UpdateOneTask {
+    impl Command<Target, i32, bool, ()> for UpdateOneTask {
         fn uuid(&self) -> Uuid {
             Uuid::new_v4()
         }
@@ -155,7 +879,7 @@ mod tests {
 
     struct UpdateTwoTask;
 
-    impl Task<Target, i32, bool, ()> for UpdateTwoTask {
+    impl Command<Target, i32, bool, ()> for UpdateTwoTask {
         fn uuid(&self) -> Uuid {
             Uuid::new_v4()
         }
@@ -176,14 +900,645 @@ mod tests {
         let mut target = Target::new();
         let mut invoker = Invoker::new(&mut target);
 
-        invoker.push(UpdateOneTask);
+        invoker.push_identified(UpdateOneTask);
         let mut one = 1;
         assert!(invoker.execute(&mut one).is_ok());
         assert_eq!(1, invoker.data().get());
 
-        invoker.push(UpdateTwoTask);
+        invoker.push_identified(UpdateTwoTask);
         let mut zero = 0;
         assert!(invoker.execute(&mut zero).is_ok());
         assert_eq!(0, invoker.data().get());
     }
+
+    #[test]
+    fn test_identified() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        let one = invoker.push_identified(UpdateOneTask);
+        let two = Identified::new(UpdateTwoTask);
+
+        assert_eq!(two.uuid(), two.uuid());
+        invoker.push(two);
+
+        assert_eq!(Ok(true), invoker.execute(&mut 1));
+        assert_eq!(Ok(true), invoker.execute(&mut 2));
+        assert_eq!(1, invoker.index());
+        // nothing left to execute, rather than starting over from the first
+        assert_eq!(Ok(false), invoker.execute(&mut 3));
+        assert_eq!(2, invoker.data().get());
+
+        assert_eq!(Ok(true), invoker.undo(&mut 4));
+        assert_eq!(0, invoker.index());
+        assert_eq!(Ok(false), invoker.undo(&mut 5));
+        assert!(!invoker.can_undo());
+        assert_eq!(5, invoker.data().get());
+        assert_eq!(one, invoker.pop().unwrap().uuid());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "push_identified")]
+    fn test_unstable_uuid() {
+        let mut target = Target::new();
+
+        Invoker::new(&mut target).push(UpdateOneTask);
+    }
+
+    struct AddTask {
+        uuid: Uuid,
+        amount: i32,
+        fail_execute: bool,
+        fail_rollback: bool,
+        crash: bool,
+    }
+
+    impl AddTask {
+        fn new(amount: i32) -> Self {
+            Self {
+                uuid: Uuid::new_v4(),
+                amount,
+                fail_execute: false,
+                fail_rollback: false,
+                crash: false,
+            }
+        }
+    }
+
+    impl Command<Target, i32, i32, String> for AddTask {
+        fn uuid(&self) -> Uuid {
+            self.uuid
+        }
+
+        fn execute(&mut self, data: &mut Target, arg: &mut i32) -> Result<i32, String> {
+            if self.fail_execute {
+                return Err(format!("execute {}", self.amount));
+            }
+
+            *arg += 1;
+            data.val += self.amount;
+
+            if self.crash {
+                panic!("crashed while executing {}", self.amount);
+            }
+
+            Ok(data.val)
+        }
+
+        fn rollback(&mut self, data: &mut Target, _arg: &mut i32) -> Result<i32, String> {
+            if self.fail_rollback {
+                return Err(format!("rollback {}", self.amount));
+            }
+
+            data.val -= self.amount;
+            Ok(data.val)
+        }
+    }
+
+    #[test]
+    fn test_run_all() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        let tasks = [AddTask::new(1), AddTask::new(2), AddTask::new(3)];
+        let uuids = tasks.iter().map(|e| e.uuid).collect::<Vec<_>>();
+
+        for task in tasks {
+            invoker.push(task);
+        }
+
+        let mut count = 0;
+        let report = invoker.run_all(&mut count);
+
+        assert!(report.is_committed());
+        assert_eq!(
+            vec![(uuids[0], 1), (uuids[1], 3), (uuids[2], 6)],
+            report.executed
+        );
+        assert!(report.rolled_back.is_empty());
+        assert_eq!(3, count);
+        assert_eq!(6, invoker.data().get());
+    }
+
+    #[test]
+    fn test_run_all_rollback() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        let mut failing = AddTask::new(4);
+        failing.fail_execute = true;
+
+        let mut stubborn = AddTask::new(2);
+        stubborn.fail_rollback = true;
+
+        let tasks = [AddTask::new(1), stubborn, AddTask::new(3), failing];
+        let uuids = tasks.iter().map(|e| e.uuid).collect::<Vec<_>>();
+
+        for task in tasks {
+            invoker.push(task);
+        }
+
+        let mut count = 0;
+        let report = invoker.run_all(&mut count);
+
+        assert!(!report.is_committed());
+        assert_eq!(3, report.executed.len());
+        assert_eq!(Some((uuids[3], "execute 4".to_string())), report.failed);
+        assert_eq!(
+            vec![
+                (uuids[2], Ok(3)),
+                (uuids[1], Err("rollback 2".to_string())),
+                (uuids[0], Ok(2)),
+            ],
+            report.rolled_back
+        );
+        assert_eq!(
+            vec![(&uuids[1], &"rollback 2".to_string())],
+            report.rollback_failures().collect::<Vec<_>>()
+        );
+        assert_eq!(2, invoker.data().get());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.push(AddTask::new(1));
+        invoker.push(AddTask::new(2));
+        invoker.push(AddTask::new(4));
+
+        let mut count = 0;
+
+        assert!(!invoker.can_undo());
+        assert!(!invoker.can_redo());
+
+        assert_eq!(Ok(1), invoker.execute(&mut count));
+        assert_eq!(Ok(3), invoker.execute(&mut count));
+        assert_eq!(Ok(7), invoker.execute(&mut count));
+        assert_eq!(Ok(0), invoker.execute(&mut count));
+        assert_eq!(3, count);
+
+        assert_eq!(Ok(3), invoker.undo(&mut count));
+        assert_eq!(Ok(1), invoker.undo(&mut count));
+        assert!(invoker.can_undo());
+        assert!(invoker.can_redo());
+
+        assert_eq!(Ok(3), invoker.redo(&mut count));
+        assert_eq!(3, invoker.data().get());
+
+        assert_eq!(Ok(1), invoker.undo(&mut count));
+        assert_eq!(Ok(0), invoker.undo(&mut count));
+        assert_eq!(Ok(0), invoker.undo(&mut count));
+        assert!(!invoker.can_undo());
+        assert_eq!(0, invoker.data().get());
+
+        assert_eq!(Ok(1), invoker.redo(&mut count));
+        assert_eq!(Ok(3), invoker.redo(&mut count));
+        assert_eq!(Ok(7), invoker.redo(&mut count));
+        assert!(!invoker.can_redo());
+        assert_eq!(Ok(0), invoker.redo(&mut count));
+        assert_eq!(7, invoker.data().get());
+    }
+
+    #[test]
+    fn test_push_truncates_redo() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.push(AddTask::new(1));
+        invoker.push(AddTask::new(2));
+        invoker.push(AddTask::new(4));
+
+        let mut count = 0;
+
+        invoker.execute(&mut count).unwrap();
+        invoker.execute(&mut count).unwrap();
+        invoker.undo(&mut count).unwrap();
+
+        assert!(invoker.can_redo());
+
+        invoker.push(AddTask::new(8));
+
+        assert!(!invoker.can_redo());
+        assert_eq!(3, invoker.len());
+
+        // the task that was never executed still runs before the new one
+        assert_eq!(Ok(5), invoker.execute(&mut count));
+        assert_eq!(Ok(13), invoker.execute(&mut count));
+    }
+
+    #[test]
+    fn test_history_depth() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.set_history_depth(Some(2));
+
+        invoker.push(AddTask::new(1));
+        invoker.push(AddTask::new(2));
+        invoker.push(AddTask::new(4));
+
+        let mut count = 0;
+        let report = invoker.run_all(&mut count);
+
+        assert!(report.is_committed());
+        assert_eq!(2, invoker.len());
+
+        assert_eq!(Ok(3), invoker.undo(&mut count));
+        assert_eq!(Ok(1), invoker.undo(&mut count));
+        assert!(!invoker.can_undo());
+        assert_eq!(Ok(0), invoker.undo(&mut count));
+        assert_eq!(1, invoker.data().get());
+    }
+
+    struct FlakyTask {
+        uuid: Uuid,
+        failures: u32,
+    }
+
+    impl Command<Target, i32, u32, String> for FlakyTask {
+        fn uuid(&self) -> Uuid {
+            self.uuid
+        }
+
+        fn execute(&mut self, data: &mut Target, arg: &mut i32) -> Result<u32, String> {
+            *arg += 1;
+
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(if self.failures > 2 { "fatal" } else { "busy" }.to_string());
+            }
+
+            data.val += 1;
+            Ok(self.failures)
+        }
+
+        fn rollback(&mut self, data: &mut Target, _arg: &mut i32) -> Result<u32, String> {
+            data.val -= 1;
+            Ok(self.failures)
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let clock = Arc::new(ManualClock::default());
+        let rolled_back = std::cell::RefCell::new(Vec::new());
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.set_clock(clock.clone());
+        invoker.subscribe(|e: &Event| {
+            if e.kind == EventKind::RolledBack {
+                rolled_back.borrow_mut().push(e.attempt);
+            }
+        });
+        invoker.set_retry_policy(Some(
+            RetryPolicy::new(3)
+                .with_backoff(Backoff::Fixed(Duration::from_secs(1)))
+                .retry_if(|e: &String| e == "busy"),
+        ));
+
+        invoker.push(FlakyTask {
+            uuid: Uuid::new_v4(),
+            failures: 2,
+        });
+        invoker.push(FlakyTask {
+            uuid: Uuid::new_v4(),
+            failures: 3,
+        });
+        invoker.push(FlakyTask {
+            uuid: Uuid::new_v4(),
+            failures: 4,
+        });
+
+        let mut attempts = 0;
+
+        assert_eq!(Ok(0), invoker.execute(&mut attempts));
+        assert_eq!(3, attempts);
+        assert_eq!(Duration::from_secs(2), elapsed(&clock));
+
+        assert_eq!(Err("busy".to_string()), invoker.execute(&mut attempts));
+        assert_eq!(6, attempts);
+
+        // the remaining failure is retried on the next call
+        assert_eq!(Ok(0), invoker.execute(&mut attempts));
+        assert_eq!(7, attempts);
+
+        assert_eq!(Err("fatal".to_string()), invoker.execute(&mut attempts));
+        assert_eq!(8, attempts);
+        assert_eq!(2, invoker.data().get());
+
+        // reported with the attempts it took to execute them
+        invoker.undo(&mut attempts).unwrap();
+        invoker.undo(&mut attempts).unwrap();
+
+        assert_eq!(vec![1, 3], *rolled_back.borrow());
+    }
+
+    fn elapsed(clock: &ManualClock) -> Duration {
+        clock.now().duration_since(SystemTime::UNIX_EPOCH).unwrap()
+    }
+
+    #[test]
+    fn test_drive_either() {
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        // its own data is set aside while the invoker calls it
+        let mut closure = crate::Task::new(Target { val: 100 });
+        closure.set_callback(|this, arg: &i32| {
+            this.data.val += *arg;
+
+            if this.data.val < 20 {
+                Ok(true)
+            } else {
+                Err(())
+            }
+        });
+        closure.set_rollback_callback(|this, arg| {
+            this.data.val -= *arg;
+
+            Ok(true)
+        });
+        closure.set_dry_run_callback(|this, arg| this.data.val + *arg < 20);
+
+        let adapter = FnCommand::new(|data: &mut Target, arg: &mut i32| {
+            data.val += *arg;
+            Ok(true)
+        })
+        .with_rollback(|data, arg| {
+            data.val -= *arg;
+            Ok(false)
+        })
+        .with_dry_run(|data, _| data.val < 10);
+
+        invoker.push(closure);
+        invoker.push(adapter);
+        invoker.push_identified(UpdateOneTask);
+
+        let mut arg = 5;
+
+        assert!(invoker.dry_run(&arg));
+        assert_eq!(Ok(true), invoker.execute(&mut arg));
+        assert_eq!(5, invoker.data().get());
+        assert_eq!(Ok(true), invoker.execute(&mut arg));
+        assert_eq!(10, invoker.data().get());
+
+        assert_eq!(Ok(false), invoker.undo(&mut arg));
+        assert_eq!(Ok(true), invoker.undo(&mut arg));
+        assert_eq!(0, invoker.data().get());
+
+        arg = 20;
+
+        assert!(!invoker.dry_run(&arg));
+        assert_eq!(Err(()), invoker.execute(&mut arg));
+    }
+
+    #[test]
+    fn test_middleware() {
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.add_middleware(crate::Hooks::new().before(|e| {
+            log.borrow_mut().push((e.operation, e.attempt));
+        }));
+        invoker.add_middleware(
+            |e: &Invocation, next: &mut dyn FnMut() -> Result<i32, String>| {
+                if e.operation == Operation::Rollback {
+                    return Err("denied".to_string());
+                }
+
+                next()
+            },
+        );
+        invoker.set_retry_policy(Some(RetryPolicy::new(2)));
+
+        let mut failing = AddTask::new(2);
+        failing.fail_execute = true;
+
+        invoker.push(AddTask::new(1));
+        invoker.push(failing);
+
+        let mut count = 0;
+
+        assert_eq!(Ok(1), invoker.execute(&mut count));
+        assert_eq!(Err("denied".to_string()), invoker.undo(&mut count));
+        assert_eq!(Err("execute 2".to_string()), invoker.execute(&mut count));
+        assert_eq!(1, invoker.data().get());
+        assert_eq!(
+            vec![
+                (Operation::Execute, 1),
+                (Operation::Rollback, 1),
+                (Operation::Execute, 1),
+                (Operation::Execute, 2),
+            ],
+            *log.borrow()
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let clock = Arc::new(ManualClock::default());
+        let slow_clock = clock.clone();
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.set_clock(clock.clone());
+        invoker.push(AddTask::new(1));
+        invoker.push(
+            FnCommand::new(move |data: &mut Target, _: &mut i32| {
+                data.val += 10;
+                slow_clock.advance(Duration::from_secs(5));
+
+                Ok(data.val)
+            })
+            .with_rollback(|data, _| {
+                data.val -= 10;
+
+                Ok(data.val)
+            })
+            .with_timeout(Duration::from_secs(2)),
+        );
+
+        let mut arg = 0;
+
+        assert_eq!(Ok(1), invoker.execute(&mut arg));
+        assert_eq!(None, invoker.last_interrupt());
+
+        // the timed out task is rolled back and the cursor stays put
+        assert_eq!(Ok(1), invoker.execute(&mut arg));
+        assert_eq!(
+            Some(Interrupt::TimedOut),
+            invoker.last_interrupt().map(|e| e.1)
+        );
+        assert_eq!(1, invoker.data().get());
+        assert_eq!(1, invoker.cursor());
+
+        invoker.undo(&mut arg).unwrap();
+        invoker.token().cancel();
+
+        let report = invoker.run_all(&mut arg);
+
+        assert!(!report.is_committed());
+        assert!(report.failed.is_none());
+        assert_eq!(Some(Interrupt::Cancelled), report.interrupted.map(|e| e.1));
+        assert_eq!(1, report.rolled_back.len());
+        assert_eq!(0, invoker.data().get());
+
+        invoker.set_token(CancellationToken::new());
+
+        assert_eq!(Ok(1), invoker.execute(&mut arg));
+
+        let slow_clock = clock.clone();
+
+        invoker.clear();
+        invoker.push(
+            FnCommand::new(move |_: &mut Target, _: &mut i32| {
+                slow_clock.advance(Duration::from_secs(5));
+
+                Err("gave up".to_string())
+            })
+            .with_rollback(|data, _| {
+                data.val = -1;
+
+                Ok(data.val)
+            })
+            .with_timeout(Duration::from_secs(2)),
+        );
+
+        // having failed, it is not rolled back
+        assert_eq!(Err("gave up".to_string()), invoker.execute(&mut arg));
+        assert_eq!(1, invoker.data().get());
+    }
+
+    #[test]
+    fn test_events() {
+        let clock = Arc::new(ManualClock::default());
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut target = Target::new();
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.set_clock(clock.clone());
+        invoker.set_retry_policy(Some(
+            RetryPolicy::new(2).with_backoff(Backoff::Fixed(Duration::from_secs(1))),
+        ));
+        invoker.subscribe(|e: &Event| {
+            log.borrow_mut()
+                .push((e.uuid, e.kind, e.attempt, elapsed(&clock)));
+        });
+
+        let mut failing = AddTask::new(2);
+        failing.fail_execute = true;
+
+        let (a, b) = (Uuid::new_v4(), failing.uuid);
+
+        invoker.push(AddTask {
+            uuid: a,
+            fail_rollback: true,
+            ..AddTask::new(1)
+        });
+        invoker.push(failing);
+
+        let mut count = 0;
+        let secs = Duration::from_secs;
+
+        invoker.execute(&mut count).unwrap();
+        invoker.execute(&mut count).unwrap_err();
+        invoker.undo(&mut count).unwrap_err();
+
+        assert_eq!(
+            vec![
+                (a, EventKind::Queued, 0, secs(0)),
+                (b, EventKind::Queued, 0, secs(0)),
+                (a, EventKind::Started, 1, secs(0)),
+                (a, EventKind::Succeeded, 1, secs(0)),
+                (b, EventKind::Started, 1, secs(0)),
+                (b, EventKind::Retried, 1, secs(0)),
+                (b, EventKind::Started, 2, secs(1)),
+                (b, EventKind::Failed, 2, secs(1)),
+                (a, EventKind::RollbackFailed, 1, secs(1)),
+            ],
+            *log.borrow()
+        );
+    }
+
+    impl From<JournalError> for String {
+        fn from(value: JournalError) -> Self {
+            value.to_string()
+        }
+    }
+
+    #[test]
+    fn test_journal() {
+        let path = std::env::temp_dir().join(format!("invoker-{}.jsonl", Uuid::new_v4()));
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut target = Target::new();
+        let mut count = 0;
+
+        {
+            let mut invoker = Invoker::new(&mut target);
+
+            invoker.set_journal(Some(Journal::open(&path).unwrap()));
+            invoker.push(AddTask {
+                uuid: a,
+                ..AddTask::new(1)
+            });
+            invoker.push(AddTask {
+                uuid: b,
+                crash: true,
+                ..AddTask::new(2)
+            });
+
+            assert_eq!(Ok(1), invoker.execute(&mut count));
+
+            let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                invoker.execute(&mut count)
+            }));
+
+            assert!(crashed.is_err());
+        }
+
+        // the second task was half way through when the process went down
+        assert_eq!(3, target.get());
+
+        let mut invoker = Invoker::new(&mut target);
+
+        invoker.set_journal(Some(Journal::open(&path).unwrap()));
+        invoker.push(AddTask {
+            uuid: a,
+            ..AddTask::new(1)
+        });
+        invoker.push(AddTask {
+            uuid: b,
+            ..AddTask::new(2)
+        });
+
+        let recovery = invoker.recover(&mut count).unwrap();
+
+        assert!(recovery.is_complete());
+        assert_eq!(vec![(b, Ok(1))], recovery.rolled_back);
+        assert_eq!(1, invoker.data().get());
+        assert!(invoker.recover(&mut count).unwrap().rolled_back.is_empty());
+
+        let entries = invoker.journal().unwrap().entries().unwrap();
+
+        assert_eq!(
+            vec![
+                (a, Operation::Execute, Phase::Started),
+                (a, Operation::Execute, Phase::Completed),
+                (b, Operation::Execute, Phase::Started),
+                (b, Operation::Rollback, Phase::Started),
+                (b, Operation::Rollback, Phase::Completed),
+            ],
+            entries
+                .iter()
+                .map(|e| (e.uuid, e.operation, e.phase))
+                .collect::<Vec<_>>()
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::executor::BoxFuture;
+use crate::{Invocation, Middleware, Observer, Task};
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Local {}
+    impl Sealed for super::Shared {}
+}
+
+// Takes any closure, but whatever holds them stays on its thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Local;
+
+// Only takes closures that are `Send + Sync`, so that tasks and managers can
+// cross threads; see `SharedTaskManager`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Shared;
+
+// How tasks, managers and middleware chains hold their closures.
+pub trait Threading: sealed::Sealed + Default + Sized + 'static {
+    type Callback<T, U, R, E>: ?Sized + Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E>;
+    type DryRun<T, U, R, E>: ?Sized + Fn(&Task<T, U, R, E, Self>, &U) -> bool;
+    type AsyncCallback<T, U, R, E>: ?Sized
+        + for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>;
+    type Layer<'a, O>: ?Sized + Middleware<O>;
+    type Observer<'a>: ?Sized + Observer;
+    type Before<'a>: ?Sized + Fn(&Invocation);
+    type After<'a, O>: ?Sized + Fn(&Invocation, &O, Duration);
+    type Route<T>: ?Sized + Fn(&T) -> bool;
+
+    fn default_callback<T, U, R: Default, E>() -> Arc<Self::Callback<T, U, R, E>>;
+    fn default_dry_run<T, U, R, E>() -> Box<Self::DryRun<T, U, R, E>>;
+}
+
+// Closures of type `F` may be held under this threading model.
+pub trait Holds<F>: Threading {
+    fn callback<T, U, R, E>(f: F) -> Arc<Self::Callback<T, U, R, E>>
+    where
+        F: Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E> + 'static;
+
+    fn dry_run<T, U, R, E>(f: F) -> Box<Self::DryRun<T, U, R, E>>
+    where
+        F: Fn(&Task<T, U, R, E, Self>, &U) -> bool + 'static;
+
+    fn async_callback<T, U, R, E>(f: F) -> Arc<Self::AsyncCallback<T, U, R, E>>
+    where
+        F: for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>
+            + 'static;
+
+    fn layer<'a, O>(f: F) -> Arc<Self::Layer<'a, O>>
+    where
+        F: Middleware<O> + 'a;
+
+    fn observer<'a>(f: F) -> Arc<Self::Observer<'a>>
+    where
+        F: Observer + 'a;
+
+    fn before<'a>(f: F) -> Box<Self::Before<'a>>
+    where
+        F: Fn(&Invocation) + 'a;
+
+    fn after<'a, O>(f: F) -> Box<Self::After<'a, O>>
+    where
+        F: Fn(&Invocation, &O, Duration) + 'a;
+
+    fn route<T>(f: F) -> Box<Self::Route<T>>
+    where
+        F: Fn(&T) -> bool + 'static;
+}
+
+impl Threading for Local {
+    type Callback<T, U, R, E> = dyn Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E>;
+    type DryRun<T, U, R, E> = dyn Fn(&Task<T, U, R, E, Self>, &U) -> bool;
+    type AsyncCallback<T, U, R, E> =
+        dyn for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>;
+    type Layer<'a, O> = dyn Middleware<O> + 'a;
+    type Observer<'a> = dyn Observer + 'a;
+    type Before<'a> = dyn Fn(&Invocation) + 'a;
+    type After<'a, O> = dyn Fn(&Invocation, &O, Duration) + 'a;
+    type Route<T> = dyn Fn(&T) -> bool;
+
+    fn default_callback<T, U, R: Default, E>() -> Arc<Self::Callback<T, U, R, E>> {
+        Arc::new(|_, _| Ok(R::default()))
+    }
+
+    fn default_dry_run<T, U, R, E>() -> Box<Self::DryRun<T, U, R, E>> {
+        Box::new(|_, _| true)
+    }
+}
+
+impl<F> Holds<F> for Local {
+    fn callback<T, U, R, E>(f: F) -> Arc<Self::Callback<T, U, R, E>>
+    where
+        F: Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E> + 'static,
+    {
+        Arc::new(f)
+    }
+
+    fn dry_run<T, U, R, E>(f: F) -> Box<Self::DryRun<T, U, R, E>>
+    where
+        F: Fn(&Task<T, U, R, E, Self>, &U) -> bool + 'static,
+    {
+        Box::new(f)
+    }
+
+    fn async_callback<T, U, R, E>(f: F) -> Arc<Self::AsyncCallback<T, U, R, E>>
+    where
+        F: for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>
+            + 'static,
+    {
+        Arc::new(f)
+    }
+
+    fn layer<'a, O>(f: F) -> Arc<Self::Layer<'a, O>>
+    where
+        F: Middleware<O> + 'a,
+    {
+        Arc::new(f)
+    }
+
+    fn observer<'a>(f: F) -> Arc<Self::Observer<'a>>
+    where
+        F: Observer + 'a,
+    {
+        Arc::new(f)
+    }
+
+    fn before<'a>(f: F) -> Box<Self::Before<'a>>
+    where
+        F: Fn(&Invocation) + 'a,
+    {
+        Box::new(f)
+    }
+
+    fn after<'a, O>(f: F) -> Box<Self::After<'a, O>>
+    where
+        F: Fn(&Invocation, &O, Duration) + 'a,
+    {
+        Box::new(f)
+    }
+
+    fn route<T>(f: F) -> Box<Self::Route<T>>
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        Box::new(f)
+    }
+}
+
+impl Threading for Shared {
+    type Callback<T, U, R, E> =
+        dyn Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E> + Send + Sync;
+    type DryRun<T, U, R, E> = dyn Fn(&Task<T, U, R, E, Self>, &U) -> bool + Send + Sync;
+    type AsyncCallback<T, U, R, E> = dyn for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>
+        + Send
+        + Sync;
+    type Layer<'a, O> = dyn Middleware<O> + Send + Sync + 'a;
+    type Observer<'a> = dyn Observer + Send + Sync + 'a;
+    type Before<'a> = dyn Fn(&Invocation) + Send + Sync + 'a;
+    type After<'a, O> = dyn Fn(&Invocation, &O, Duration) + Send + Sync + 'a;
+    type Route<T> = dyn Fn(&T) -> bool + Send + Sync;
+
+    fn default_callback<T, U, R: Default, E>() -> Arc<Self::Callback<T, U, R, E>> {
+        Arc::new(|_, _| Ok(R::default()))
+    }
+
+    fn default_dry_run<T, U, R, E>() -> Box<Self::DryRun<T, U, R, E>> {
+        Box::new(|_, _| true)
+    }
+}
+
+impl<F: Send + Sync> Holds<F> for Shared {
+    fn callback<T, U, R, E>(f: F) -> Arc<Self::Callback<T, U, R, E>>
+    where
+        F: Fn(&mut Task<T, U, R, E, Self>, &U) -> Result<R, E> + 'static,
+    {
+        Arc::new(f)
+    }
+
+    fn dry_run<T, U, R, E>(f: F) -> Box<Self::DryRun<T, U, R, E>>
+    where
+        F: Fn(&Task<T, U, R, E, Self>, &U) -> bool + 'static,
+    {
+        Box::new(f)
+    }
+
+    fn async_callback<T, U, R, E>(f: F) -> Arc<Self::AsyncCallback<T, U, R, E>>
+    where
+        F: for<'a> Fn(&'a mut Task<T, U, R, E, Self>, &'a U) -> BoxFuture<'a, Result<R, E>>
+            + 'static,
+    {
+        Arc::new(f)
+    }
+
+    fn layer<'a, O>(f: F) -> Arc<Self::Layer<'a, O>>
+    where
+        F: Middleware<O> + 'a,
+    {
+        Arc::new(f)
+    }
+
+    fn observer<'a>(f: F) -> Arc<Self::Observer<'a>>
+    where
+        F: Observer + 'a,
+    {
+        Arc::new(f)
+    }
+
+    fn before<'a>(f: F) -> Box<Self::Before<'a>>
+    where
+        F: Fn(&Invocation) + 'a,
+    {
+        Box::new(f)
+    }
+
+    fn after<'a, O>(f: F) -> Box<Self::After<'a, O>>
+    where
+        F: Fn(&Invocation, &O, Duration) + 'a,
+    {
+        Box::new(f)
+    }
+
+    fn route<T>(f: F) -> Box<Self::Route<T>>
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        Box::new(f)
+    }
+}